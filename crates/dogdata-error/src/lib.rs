@@ -0,0 +1,156 @@
+//! Datadog error tracking fields for [`std::error::Error`] values, shared by `dogdata` and
+//! its axum, reqwest and sqlx integrations.
+//!
+//! Error Tracking groups failed spans by their `error.type`, `error.message` and
+//! `error.stack` tags. [`record_error`] fills them in, along with the span status:
+//!
+//! ```
+//! use tracing::field::Empty;
+//!
+//! let span = tracing::info_span!(
+//!     "charge",
+//!     otel.status_code = Empty,
+//!     error.type = Empty,
+//!     error.message = Empty,
+//!     error.stack = Empty,
+//! );
+//! if let Err(err) = "forty-two".parse::<u32>() {
+//!     dogdata_error::record_error(&span, &err);
+//! }
+//! ```
+//!
+//! Like any [`tracing`] field, they have to be declared when the span is created.
+//!
+//! `error.type` is the name of the static type of the error, see [`error_type_name`]. A
+//! boxed `dyn Error` does not tell its concrete type, so `error.type` is left empty for it
+//! unless given to [`record_error_with_type`].
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::error::Error;
+use std::fmt::Write;
+
+/// The type name of the error.
+pub const ERROR_TYPE: &str = "error.type";
+/// The `Display` output of the error.
+pub const ERROR_MESSAGE: &str = "error.message";
+/// The chain of sources of the error, followed by the backtrace when captured.
+pub const ERROR_STACK: &str = "error.stack";
+
+/// Marks the span as failed and records the error tracking fields of `error`.
+///
+/// A backtrace of the caller is appended to `error.stack` when enabled with
+/// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`, see [`Backtrace::capture`].
+pub fn record_error<E>(span: &tracing::Span, error: &E)
+where
+    E: Error + ?Sized,
+{
+    if let Some(error_type) = error_type_name::<E>() {
+        span.record(ERROR_TYPE, error_type);
+    }
+    record_error_fields(span, error);
+}
+
+/// Like [`record_error`], with the `error.type` given by the caller, for errors whose type
+/// has been erased.
+pub fn record_error_with_type<E>(span: &tracing::Span, error: &E, error_type: &str)
+where
+    E: Error + ?Sized,
+{
+    span.record(ERROR_TYPE, error_type);
+    record_error_fields(span, error);
+}
+
+fn record_error_fields<E>(span: &tracing::Span, error: &E)
+where
+    E: Error + ?Sized,
+{
+    span.record("otel.status_code", "ERROR");
+    span.record(ERROR_MESSAGE, error.to_string());
+    span.record(ERROR_STACK, error_stack(error, &Backtrace::capture()));
+}
+
+/// The name of the error type `E`, or `None` for a trait object such as `Box<dyn Error>`,
+/// whose concrete type is only known at runtime.
+///
+/// ```
+/// use dogdata_error::error_type_name;
+/// use std::error::Error;
+///
+/// assert!(error_type_name::<std::num::ParseIntError>().is_some());
+/// assert_eq!(error_type_name::<Box<dyn Error + Send + Sync>>(), None);
+/// ```
+pub fn error_type_name<E>() -> Option<&'static str>
+where
+    E: ?Sized,
+{
+    let name = std::any::type_name::<E>();
+    (!name.contains("dyn ")).then_some(name)
+}
+
+/// Formats the error the way `anyhow` does: the message, its causes, then the backtrace.
+pub fn error_stack<E>(error: &E, backtrace: &Backtrace) -> String
+where
+    E: Error + ?Sized,
+{
+    let mut stack = error.to_string();
+    let mut source = error.source();
+    if source.is_some() {
+        stack.push_str("\n\nCaused by:");
+    }
+    let mut index = 0;
+    while let Some(cause) = source {
+        let _ = write!(stack, "\n    {index}: {cause}");
+        source = cause.source();
+        index += 1;
+    }
+    if backtrace.status() == BacktraceStatus::Captured {
+        let _ = write!(stack, "\n\nStack backtrace:\n{backtrace}");
+    }
+    stack
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct ChargeError(std::io::Error);
+
+    impl fmt::Display for ChargeError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("failed to charge the card")
+        }
+    }
+
+    impl Error for ChargeError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_error_stack_lists_causes() {
+        let inner = std::io::Error::other(ChargeError(std::io::Error::other("timed out")));
+        let error = ChargeError(inner);
+
+        assert_eq!(
+            error_stack(&error, &Backtrace::disabled()),
+            "failed to charge the card\n\nCaused by:\n    0: failed to charge the card\n    1: timed out"
+        );
+        assert_eq!(
+            error_stack(&std::io::Error::other("timed out"), &Backtrace::disabled()),
+            "timed out"
+        );
+    }
+
+    #[test]
+    fn test_error_stack_appends_backtrace() {
+        let stack = error_stack(
+            &std::io::Error::other("timed out"),
+            &Backtrace::force_capture(),
+        );
+
+        assert!(stack.starts_with("timed out\n\nStack backtrace:\n"));
+    }
+}
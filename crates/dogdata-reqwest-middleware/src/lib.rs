@@ -105,12 +105,14 @@
 //! ```
 
 mod middleware;
-#[cfg(any(feature = "opentelemetry_0_30",))]
+#[cfg(any(feature = "opentelemetry_0_28", feature = "opentelemetry_0_30"))]
 mod otel;
 mod reqwest_otel_span_builder;
 pub use middleware::TracingMiddleware;
+#[allow(deprecated)]
+pub use reqwest_otel_span_builder::ERROR_CAUSE_CHAIN;
 pub use reqwest_otel_span_builder::{
-    DefaultSpanBackend, DisableOtelPropagation, ERROR_CAUSE_CHAIN, ERROR_MESSAGE,
+    DefaultSpanBackend, DisableOtelPropagation, ERROR_MESSAGE, ERROR_STACK, ERROR_TYPE,
     HTTP_REQUEST_METHOD, HTTP_RESPONSE_STATUS_CODE, OTEL_KIND, OTEL_NAME, OTEL_STATUS_CODE,
     OtelName, OtelPathNames, ReqwestOtelSpanBackend, SERVER_ADDRESS, SERVER_PORT,
     SpanBackendWithUrl, URL_FULL, URL_SCHEME, USER_AGENT_ORIGINAL, default_on_request_end,
@@ -26,7 +26,17 @@ use std::str::FromStr;
 use tracing::Span;
 
 /// Injects the given OpenTelemetry Context into a reqwest::Request headers to allow propagation downstream.
+///
+/// The context is injected with the global propagator of each enabled OpenTelemetry version,
+/// which for `opentelemetry_0_28` is the one configured by `dogdata`.
 pub fn inject_opentelemetry_context_into_request(mut request: Request) -> Request {
+    #[cfg(feature = "opentelemetry_0_28")]
+    opentelemetry_0_28_pkg::global::get_text_map_propagator(|injector| {
+        use tracing_opentelemetry_0_29_pkg::OpenTelemetrySpanExt;
+        let context = Span::current().context();
+        injector.inject_context(&context, &mut RequestCarrier::new(&mut request))
+    });
+
     #[cfg(feature = "opentelemetry_0_30")]
     opentelemetry_0_30_pkg::global::get_text_map_propagator(|injector| {
         use tracing_opentelemetry_0_31_pkg::OpenTelemetrySpanExt;
@@ -61,6 +71,13 @@ impl RequestCarrier<'_> {
     }
 }
 
+#[cfg(feature = "opentelemetry_0_28")]
+impl opentelemetry_0_28_pkg::propagation::Injector for RequestCarrier<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.set_inner(key, value)
+    }
+}
+
 #[cfg(feature = "opentelemetry_0_30")]
 impl opentelemetry_0_30_pkg::propagation::Injector for RequestCarrier<'_> {
     fn set(&mut self, key: &str, value: String) {
@@ -89,6 +106,22 @@ mod test {
                     .with_target("dogdata_reqwest_middleware::otel::test", Level::DEBUG),
             );
 
+            #[cfg(feature = "opentelemetry_0_28")]
+            let subscriber = {
+                use opentelemetry_0_28_pkg::trace::TracerProvider;
+
+                let provider = opentelemetry_sdk_0_28::trace::SdkTracerProvider::builder().build();
+
+                let tracer = provider.tracer("reqwest");
+                opentelemetry_0_28_pkg::global::set_tracer_provider(provider);
+                opentelemetry_0_28_pkg::global::set_text_map_propagator(
+                    opentelemetry_sdk_0_28::propagation::TraceContextPropagator::new(),
+                );
+
+                let telemetry = tracing_opentelemetry_0_29_pkg::layer().with_tracer(tracer);
+                subscriber.with(telemetry)
+            };
+
             #[cfg(feature = "opentelemetry_0_30")]
             let subscriber = {
                 use opentelemetry_0_30_pkg::trace::TracerProvider;
@@ -22,6 +22,7 @@
 
 use std::borrow::Cow;
 
+use dogdata_error::record_error;
 use http::Extensions;
 use matchit::Router;
 use reqwest::{Request, Response, StatusCode as RequestStatusCode, Url};
@@ -50,9 +51,14 @@ pub const OTEL_NAME: &str = "otel.name";
 pub const OTEL_STATUS_CODE: &str = "otel.status_code";
 /// The `http.response.status_code` field added to the span by [`reqwest_otel_span`]
 pub const HTTP_RESPONSE_STATUS_CODE: &str = "http.response.status_code";
+/// The `error.type` field added to the span by [`reqwest_otel_span`]
+pub const ERROR_TYPE: &str = dogdata_error::ERROR_TYPE;
 /// The `error.message` field added to the span by [`reqwest_otel_span`]
-pub const ERROR_MESSAGE: &str = "error.message";
-/// The `error.cause_chain` field added to the span by [`reqwest_otel_span`]
+pub const ERROR_MESSAGE: &str = dogdata_error::ERROR_MESSAGE;
+/// The `error.stack` field added to the span by [`reqwest_otel_span`]
+pub const ERROR_STACK: &str = dogdata_error::ERROR_STACK;
+/// The `error.cause_chain` field, formerly added to the span by [`reqwest_otel_span`]
+#[deprecated(note = "the cause chain is recorded in `error.stack`, see `ERROR_STACK`")]
 pub const ERROR_CAUSE_CHAIN: &str = "error.cause_chain";
 
 /// [`ReqwestOtelSpanBackend`] allows you to customise the span attached by
@@ -92,15 +98,14 @@ pub fn default_on_request_success(span: &Span, response: &Response) {
 /// Populates default failure fields for a given [`reqwest_otel_span!`] span.
 #[inline]
 pub fn default_on_request_failure(span: &Span, e: &Error) {
-    let error_message = e.to_string();
-    let error_cause_chain = format!("{e:?}");
-    span.record(OTEL_STATUS_CODE, "ERROR");
-    span.record(ERROR_MESSAGE, error_message.as_str());
-    span.record(ERROR_CAUSE_CHAIN, error_cause_chain.as_str());
-    if let Error::Reqwest(e) = e {
-        if let Some(status) = e.status() {
-            span.record("http.status_code", status.as_u16());
+    match e {
+        Error::Reqwest(e) => {
+            record_error(span, e);
+            if let Some(status) = e.status() {
+                span.record("http.status_code", status.as_u16());
+            }
         }
+        Error::Middleware(_) => record_error(span, e),
     }
 }
 
@@ -176,6 +176,10 @@ pub mod private {
                 http.method = %$method,
                 http.status_code = tracing::field::Empty,
                 http.url = tracing::field::Empty,
+                otel.status_code = tracing::field::Empty,
+                error.type = tracing::field::Empty,
+                error.message = tracing::field::Empty,
+                error.stack = tracing::field::Empty,
                 out.host = %$host,
                 otel.kind = "client",
                 span.kind = "client",
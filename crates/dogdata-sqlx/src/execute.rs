@@ -1,9 +1,10 @@
+use dogdata_error::record_error;
 #[cfg(feature = "mysql")]
 use sqlx::MySqlPool;
 #[cfg(feature = "postgres")]
 use sqlx::PgPool;
 use sqlx::{Database, Execute, Executor};
-use tracing::Instrument;
+use tracing::{Instrument, Span};
 
 use crate::sqlx_otel_span_macro::query_span_with_metadata;
 
@@ -15,6 +16,18 @@ pub struct ConnectionInfo {
     pub system: &'static str,
 }
 
+/// Runs the query in its span, recording the error on the span when it fails.
+async fn instrument_query<T>(
+    span: Span,
+    query: impl Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, sqlx::Error> {
+    let result = query.instrument(span.clone()).await;
+    if let Err(err) = &result {
+        record_error(&span, err);
+    }
+    result
+}
+
 pub trait InstrumentedPool: Sized {
     type Database: Database;
 
@@ -120,7 +133,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_one(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.fetch_one(pool.as_executor())).await
     }
 
     async fn fetch_optional_instrumented<P>(
@@ -133,9 +146,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_optional(pool.as_executor())
-            .instrument(span)
-            .await
+        instrument_query(span, self.fetch_optional(pool.as_executor())).await
     }
 
     async fn fetch_all_instrumented<P>(
@@ -148,7 +159,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_all(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.fetch_all(pool.as_executor())).await
     }
 }
 
@@ -171,7 +182,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_one(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.fetch_one(pool.as_executor())).await
     }
 
     async fn fetch_optional_instrumented<P>(
@@ -184,9 +195,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_optional(pool.as_executor())
-            .instrument(span)
-            .await
+        instrument_query(span, self.fetch_optional(pool.as_executor())).await
     }
 
     async fn fetch_all_instrumented<P>(
@@ -199,7 +208,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_all(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.fetch_all(pool.as_executor())).await
     }
 }
 
@@ -222,7 +231,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_one(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.fetch_one(pool.as_executor())).await
     }
 
     async fn fetch_optional_instrumented<P>(
@@ -235,9 +244,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_optional(pool.as_executor())
-            .instrument(span)
-            .await
+        instrument_query(span, self.fetch_optional(pool.as_executor())).await
     }
 
     async fn fetch_all_instrumented<P>(
@@ -250,7 +257,7 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.fetch_all(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.fetch_all(pool.as_executor())).await
     }
 }
 
@@ -269,6 +276,6 @@ where
         for<'c> &'c P: Executor<'c, Database = DB>,
     {
         let span = query_span_with_metadata(sql.as_ref(), pool);
-        self.execute(pool.as_executor()).instrument(span).await
+        instrument_query(span, self.execute(pool.as_executor())).await
     }
 }
@@ -19,6 +19,10 @@ macro_rules! query_span_with_metadata {
             db.system = %$metadata.system,
             db.instance = %$metadata.database,
             db.name = %$metadata.database,
+            otel.status_code = tracing::field::Empty,
+            error.type = tracing::field::Empty,
+            error.message = tracing::field::Empty,
+            error.stack = tracing::field::Empty,
         )
     }};
 }
@@ -0,0 +1,235 @@
+//! Transport to the Datadog agent.
+//!
+//! The agent is reached either over TCP (`http://host:port`) or, when it is only exposed
+//! through a socket on the node, over HTTP on a Unix domain socket
+//! (`unix:///var/run/datadog/apm.socket`). Both are configured with `DD_TRACE_AGENT_URL`.
+
+use std::fmt;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use opentelemetry::trace::TraceError;
+use opentelemetry_http::{Bytes, HttpClient, HttpError, Request, Response};
+
+/// Base URL handed to the exporter when talking over a Unix socket. Only the path of the
+/// requests is used, the host merely fills in the `Host` header.
+#[cfg(unix)]
+const UNIX_SOCKET_BASE_URL: &str = "http://localhost";
+
+/// Where the Datadog agent is listening.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentEndpoint {
+    /// `http://host:port`
+    Http(String),
+    /// `unix:///path/to/apm.socket`
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for AgentEndpoint {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(AgentEndpoint::Http(url.trim_end_matches('/').to_string()));
+        }
+
+        if let Some(path) = url.strip_prefix("unix://") {
+            #[cfg(unix)]
+            return match path {
+                "" => Err(format!("missing socket path in agent url `{url}`")),
+                path => Ok(AgentEndpoint::Unix(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!(
+                "unix sockets are not supported on this platform: `{path}`"
+            ));
+        }
+
+        Err(format!(
+            "unsupported agent url `{url}`, expected an http:// or unix:// scheme"
+        ))
+    }
+}
+
+impl fmt::Display for AgentEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentEndpoint::Http(url) => f.write_str(url),
+            #[cfg(unix)]
+            AgentEndpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+impl AgentEndpoint {
+    /// The base URL requests are built against.
+    pub(crate) fn base_url(&self) -> &str {
+        match self {
+            AgentEndpoint::Http(url) => url,
+            #[cfg(unix)]
+            AgentEndpoint::Unix(_) => UNIX_SOCKET_BASE_URL,
+        }
+    }
+
+    /// An HTTP client that reaches the agent over the right transport.
+    pub(crate) fn client(&self) -> Result<AgentClient, TraceError> {
+        match self {
+            AgentEndpoint::Http(_) => reqwest::ClientBuilder::new()
+                // disabling connection reuse with dd-agent to avoid "connection closed from server" errors
+                .pool_idle_timeout(Duration::from_millis(1))
+                .build()
+                .map(AgentClient::Http)
+                .map_err(|err| {
+                    TraceError::from(format!("failed to create the agent client: {err}"))
+                }),
+            #[cfg(unix)]
+            AgentEndpoint::Unix(path) => {
+                Ok(AgentClient::Unix(UnixSocketClient { path: path.clone() }))
+            }
+        }
+    }
+}
+
+/// [`HttpClient`] used by the exporters to talk to the agent.
+#[derive(Debug, Clone)]
+pub(crate) enum AgentClient {
+    Http(reqwest::Client),
+    #[cfg(unix)]
+    Unix(UnixSocketClient),
+}
+
+#[async_trait]
+impl HttpClient for AgentClient {
+    async fn send_bytes(&self, request: Request<Bytes>) -> Result<Response<Bytes>, HttpError> {
+        match self {
+            AgentClient::Http(client) => client.send_bytes(request).await,
+            #[cfg(unix)]
+            AgentClient::Unix(client) => client.send_bytes(request).await,
+        }
+    }
+}
+
+/// HTTP/1.1 over a Unix domain socket, one connection per request.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub(crate) struct UnixSocketClient {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl HttpClient for UnixSocketClient {
+    async fn send_bytes(&self, request: Request<Bytes>) -> Result<Response<Bytes>, HttpError> {
+        use http_body_util::{BodyExt, Full};
+        use hyper_util::rt::TokioIo;
+
+        let stream = tokio::net::UnixStream::connect(&self.path).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+
+        let (mut parts, body) = request.into_parts();
+        if let Some(host) = parts.uri.host()
+            && !parts.headers.contains_key(http::header::HOST)
+        {
+            parts.headers.insert(http::header::HOST, host.parse()?);
+        }
+        // the request target is sent in origin-form, the socket already identifies the server
+        parts.uri = parts
+            .uri
+            .path_and_query()
+            .map_or("/", |it| it.as_str())
+            .parse()?;
+
+        let response = sender
+            .send_request(Request::from_parts(parts, Full::new(body)))
+            .await?;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_http_endpoint() {
+        let endpoint: AgentEndpoint = "http://agent:8126/".parse().unwrap();
+
+        assert_eq!(
+            endpoint,
+            AgentEndpoint::Http("http://agent:8126".to_string())
+        );
+        assert_eq!(endpoint.base_url(), "http://agent:8126");
+    }
+
+    #[test]
+    fn test_parse_invalid_endpoint() {
+        assert!("tcp://agent:8126".parse::<AgentEndpoint>().is_err());
+        assert!("agent:8126".parse::<AgentEndpoint>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_unix_endpoint() {
+        let endpoint: AgentEndpoint = "unix:///var/run/datadog/apm.socket".parse().unwrap();
+
+        assert_eq!(
+            endpoint,
+            AgentEndpoint::Unix(PathBuf::from("/var/run/datadog/apm.socket"))
+        );
+        assert_eq!(endpoint.to_string(), "unix:///var/run/datadog/apm.socket");
+        assert!("unix://".parse::<AgentEndpoint>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_client_sends_request() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("dogdata-{}.socket", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let agent = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            while !received.ends_with(b"payload") {
+                let n = stream.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nOK")
+                .await
+                .unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        let endpoint = AgentEndpoint::Unix(path.clone());
+        let request = Request::post(format!("{}/v0.5/traces", endpoint.base_url()))
+            .body(Bytes::from_static(b"payload"))
+            .unwrap();
+        let response = endpoint
+            .client()
+            .unwrap()
+            .send_bytes(request)
+            .await
+            .unwrap();
+
+        let received = agent.await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"OK");
+        assert!(received.starts_with("POST /v0.5/traces HTTP/1.1\r\n"));
+        assert!(received.contains("host: localhost\r\n"));
+    }
+}
@@ -0,0 +1,269 @@
+//! Writers behind the non-blocking log layer: stdout, stderr, or a rotated file.
+//!
+//! Time based rotation is left to [`RollingFileAppender`], which does not rotate on size,
+//! hence [`SizeRollingFile`]. When the non-blocking writer is lossy, the lines it drops are
+//! reported by a [`DroppedLinesReporter`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use tracing_appender::non_blocking::ErrorCounter;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+use crate::config::{DogdataConfig, LogOutput, LogRotation};
+use crate::metrics;
+
+/// Interval between two reports of the dropped log lines.
+pub(crate) const DROPPED_LINES_INTERVAL: Duration = Duration::from_secs(10);
+
+const DROPPED_LINES: &str = "dogdata.log.dropped_lines";
+
+/// Opens the [`LogOutput`] of the configuration.
+pub(crate) fn log_writer(config: &DogdataConfig) -> io::Result<Box<dyn Write + Send>> {
+    let path = match config.log_output() {
+        LogOutput::Stdout => return Ok(Box::new(io::stdout())),
+        LogOutput::Stderr => return Ok(Box::new(io::stderr())),
+        LogOutput::File(path) => path,
+    };
+    let rotation = match config.log_rotation() {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Size(max_size) => {
+            let file = SizeRollingFile::new(path.clone(), max_size, config.log_max_files())?;
+            return Ok(Box::new(file));
+        }
+    };
+
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` is not a file path", path.display()),
+        )
+    })?;
+    let directory = path
+        .parent()
+        .filter(|it| !it.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name.to_string_lossy());
+    if let Some(max_files) = config.log_max_files() {
+        builder = builder.max_log_files(max_files);
+    }
+    let appender = builder.build(directory).map_err(io::Error::other)?;
+    Ok(Box::new(appender))
+}
+
+/// A file moved to `<path>.1` once it would exceed `max_size` bytes, after moving the
+/// previous `<path>.N` to `<path>.N+1`.
+///
+/// `max_files` counts the file being written, like the `max_log_files` of
+/// [`RollingFileAppender`].
+struct SizeRollingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: Option<usize>,
+    file: File,
+    size: u64,
+}
+
+impl SizeRollingFile {
+    fn new(path: PathBuf, max_size: u64, max_files: Option<usize>) -> io::Result<Self> {
+        if let Some(directory) = path.parent().filter(|it| !it.as_os_str().is_empty()) {
+            fs::create_dir_all(directory)?;
+        }
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let keep = self.max_files.map_or(usize::MAX, |it| it.saturating_sub(1));
+        let mut end = 1;
+        while self.rotated(end).exists() {
+            end += 1;
+        }
+        for index in keep.max(1)..end {
+            fs::remove_file(self.rotated(index))?;
+        }
+        for index in (1..end.min(keep)).rev() {
+            fs::rename(self.rotated(index), self.rotated(index + 1))?;
+        }
+        if keep > 0 {
+            fs::rename(&self.path, self.rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Reports the lines dropped by a lossy non-blocking writer on an interval, as a warning
+/// and a count through the global [`metrics`] client, until stopped or dropped.
+#[derive(Debug)]
+pub(crate) struct DroppedLinesReporter {
+    stop: Sender<()>,
+}
+
+impl DroppedLinesReporter {
+    pub(crate) fn start(counter: ErrorCounter, interval: Duration) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("dogdata-dropped-logs".to_string())
+            .spawn(move || {
+                let mut reported = 0;
+                // the channel disconnects when the reporter is dropped
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    reported = report_dropped_lines(&counter, reported);
+                }
+            })?;
+
+        Ok(Self { stop })
+    }
+
+    pub(crate) fn stop(&self) {
+        let _ = self.stop.send(());
+    }
+}
+
+/// Reports the lines dropped since the `reported` total, returning the new total.
+fn report_dropped_lines(counter: &ErrorCounter, reported: usize) -> usize {
+    let dropped = counter.dropped_lines();
+    let delta = dropped.saturating_sub(reported);
+    if delta > 0 {
+        // likely to be dropped as well while the writer is still behind
+        tracing::warn!(
+            target: "dogdata::log",
+            dropped_lines = delta,
+            "dropped {delta} log lines, the log writer cannot keep up"
+        );
+        metrics::count(DROPPED_LINES, delta as i64, &[]);
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_appender::non_blocking::NonBlockingBuilder;
+
+    /// Blocks the writer thread for as long as the lock is held.
+    struct BlockedWriter(Arc<Mutex<()>>);
+
+    impl Write for BlockedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _lock = self.0.lock().unwrap();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dogdata-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_max_files() {
+        let dir = temp_dir("size-rotation");
+        let path = dir.join("app.log");
+        let mut file = SizeRollingFile::new(path.clone(), 20, Some(3)).unwrap();
+
+        for line in 0..7 {
+            file.write_all(format!("line {line:04}\n").as_bytes())
+                .unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "line 0006\n");
+        assert_eq!(read(dir.join("app.log.1")), "line 0004\nline 0005\n");
+        assert_eq!(read(dir.join("app.log.2")), "line 0002\nline 0003\n");
+        assert!(!dir.join("app.log.3").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_output_without_rotation() {
+        let dir = temp_dir("no-rotation");
+        let path = dir.join("app.log");
+        let config = DogdataConfig::default().with_log_output(LogOutput::File(path.clone()));
+
+        let mut writer = log_writer(&config).unwrap();
+        writer.write_all(b"started\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "started\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_report_dropped_lines() {
+        let lock = Arc::new(Mutex::new(()));
+        let blocked = lock.lock().unwrap();
+        let (mut writer, guard) = NonBlockingBuilder::default()
+            .buffered_lines_limit(1)
+            .lossy(true)
+            .finish(BlockedWriter(lock.clone()));
+        let counter = writer.error_counter();
+
+        for _ in 0..10 {
+            writer.write_all(b"line\n").unwrap();
+        }
+        let dropped = counter.dropped_lines();
+        assert!(dropped >= 8);
+        assert_eq!(report_dropped_lines(&counter, 0), dropped);
+        assert_eq!(report_dropped_lines(&counter, dropped), dropped);
+
+        drop(blocked);
+        drop(guard);
+    }
+}
@@ -56,7 +56,9 @@ pub fn make_span_from_request<B>(req: &http::Request<B>) -> tracing::Span {
         otel.status_code = Empty, // to set on response
         trace_id = Empty, // to set on response
         request_id = Empty, // to set
-        exception.message = Empty, // to set on response
+        error.type = Empty, // to set on error
+        error.message = Empty, // to set on error
+        error.stack = Empty, // to set on error
         "span.type" = "web", // non-official open-telemetry key, only supported by Datadog
     )
 }
@@ -81,12 +83,8 @@ pub fn update_span_from_error<E>(span: &tracing::Span, error: &E)
 where
     E: Error,
 {
-    span.record("otel.status_code", "ERROR");
     //span.record("http.status_code", 500);
-    span.record("exception.message", error.to_string());
-    error
-        .source()
-        .map(|s| span.record("exception.message", s.to_string()));
+    crate::error::record_error(span, error);
 }
 
 pub fn update_span_from_response_or_error<B, E>(
@@ -0,0 +1,118 @@
+//! Admin endpoint reading and changing the log directives through a [`LogLevelHandle`].
+
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::log_level::{LogLevelError, LogLevelHandle};
+
+/// A router serving the log directives of `handle` on `/`:
+///
+/// - `GET` returns `{"directives": "info", "revert_in_secs": null}`
+/// - `PUT` with `{"directives": "debug,hyper=info", "revert_after_secs": 600}` replaces them,
+///   and restores the current ones after `revert_after_secs` when set
+///
+/// It has no authentication of its own, so it is meant to be nested under an admin path
+/// that is not exposed publicly:
+///
+/// ```no_run
+/// use axum::Router;
+/// use dogdata::axum::log_level_router;
+///
+/// # async {
+/// let (_guard, shutdown, log_level) = dogdata::init(dogdata::DogdataConfig::from_env()).unwrap();
+/// let admin: Router = Router::new().nest("/admin/log-level", log_level_router(log_level));
+///
+/// let listener = tokio::net::TcpListener::bind("127.0.0.1:9090").await.unwrap();
+/// axum::serve(listener, admin).await.unwrap();
+/// # shutdown.shutdown();
+/// # };
+/// ```
+pub fn log_level_router<S>(handle: LogLevelHandle) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/", get(get_log_level).put(put_log_level))
+        .with_state(handle)
+}
+
+#[derive(Serialize)]
+struct LogLevelResponse {
+    directives: String,
+    revert_in_secs: Option<u64>,
+}
+
+impl From<&LogLevelHandle> for LogLevelResponse {
+    fn from(handle: &LogLevelHandle) -> Self {
+        Self {
+            directives: handle.directives(),
+            revert_in_secs: handle.revert_in().map(|it| it.as_secs()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LogLevelRequest {
+    directives: String,
+    revert_after_secs: Option<u64>,
+}
+
+async fn get_log_level(State(handle): State<LogLevelHandle>) -> Json<LogLevelResponse> {
+    Json(LogLevelResponse::from(&handle))
+}
+
+async fn put_log_level(
+    State(handle): State<LogLevelHandle>,
+    Json(request): Json<LogLevelRequest>,
+) -> Response {
+    let result = match request.revert_after_secs {
+        Some(secs) => handle.set_for(&request.directives, Duration::from_secs(secs)),
+        None => handle.set(&request.directives),
+    };
+    match result {
+        Ok(()) => Json(LogLevelResponse::from(&handle)).into_response(),
+        Err(err) => {
+            let status = match err {
+                LogLevelError::Parse(_) | LogLevelError::Delay(_) => StatusCode::BAD_REQUEST,
+                LogLevelError::Reload(_) | LogLevelError::Revert(_) => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            (status, err.to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, header};
+    use tower::Service;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::{EnvFilter, Registry, reload};
+
+    #[tokio::test]
+    async fn test_put_rejects_out_of_range_revert() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(filter);
+        let mut router: Router = log_level_router(LogLevelHandle::new(handle, "info", "debug"));
+
+        let request = Request::put("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(
+                r#"{{"directives": "trace", "revert_after_secs": {}}}"#,
+                u64::MAX
+            )))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -27,7 +27,8 @@
 //!
 //! Also, exposes OtelAxumLayer from the same project, but hacked to support datadog.
 //!
-//! Additionally, a shutdown helper function named `shutdown_signal` is also exposed
+//! Additionally, a shutdown helper function named `shutdown_signal` is also exposed, as well
+//! as `log_level_router`, an admin router to change the log directives at runtime.
 
 mod shutdown;
 pub use shutdown::*;
@@ -39,3 +40,6 @@ pub use middleware::*;
 pub use axum_tracing_opentelemetry::middleware::OtelInResponseLayer;
 
 mod http_server;
+
+mod log_level;
+pub use log_level::*;
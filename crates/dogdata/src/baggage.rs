@@ -0,0 +1,223 @@
+//! Request-scoped values carried across service hops in the W3C `baggage` header.
+//!
+//! Baggage lives in the OpenTelemetry context of the current span, so every span started
+//! below it sees the same values. The axum layer extracts it from incoming requests and the
+//! reqwest middleware injects it into outgoing ones, as long as `baggage` is one of the
+//! [propagation styles](crate::propagator::PropagationStyle), which it is by default.
+//!
+//! ```no_run
+//! #[tracing::instrument]
+//! async fn checkout(tenant: &str) {
+//!     dogdata::baggage::set("tenant.id", tenant.to_string());
+//!
+//!     // every request sent from here on carries `baggage: tenant.id=...`
+//!     assert!(dogdata::baggage::get("tenant.id").is_some());
+//! }
+//! ```
+//!
+//! The keys listed in `DD_TRACE_BAGGAGE_TAG_KEYS`, none by default, are also written to the
+//! spans as `baggage.<key>` tags.
+
+use opentelemetry::baggage::{Baggage, BaggageExt};
+use opentelemetry::{Context, Key, KeyValue, StringValue};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use tracing::dispatcher;
+use tracing_opentelemetry::{OpenTelemetrySpanExt, OtelData};
+use tracing_subscriber::Registry;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Prefix of the span tags copied from baggage.
+const BAGGAGE_TAG_PREFIX: &str = "baggage.";
+
+/// Sets a baggage entry on the current span. Does nothing outside of a span, or when the
+/// subscriber is not built on a [`Registry`].
+pub fn set<K, V>(key: K, value: V)
+where
+    K: Into<Key>,
+    V: Into<StringValue>,
+{
+    let entry = KeyValue::new(key.into(), value.into());
+    update_current_context(|cx| cx.with_baggage([entry.clone()]));
+}
+
+/// Removes a baggage entry from the current span.
+pub fn remove(key: &str) {
+    update_current_context(|cx| {
+        let baggage: Baggage = cx
+            .baggage()
+            .iter()
+            .filter(|(it, _)| it.as_str() != key)
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        cx.with_value(baggage)
+    });
+}
+
+/// Returns the value of a baggage entry of the current span.
+pub fn get(key: &str) -> Option<String> {
+    tracing::Span::current()
+        .context()
+        .baggage()
+        .get(key)
+        .map(|value| value.as_str().to_string())
+}
+
+fn update_current_context(update: impl Fn(&Context) -> Context) {
+    dispatcher::get_default(|dispatch| {
+        let Some(id) = dispatch.current_span().id().cloned() else {
+            return;
+        };
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        let Some(span) = registry.span(&id) else {
+            return;
+        };
+
+        // spans started from now on inherit the parent context of this one
+        if let Some(data) = span.extensions_mut().get_mut::<OtelData>() {
+            data.parent_cx = update(&data.parent_cx);
+        }
+    });
+}
+
+/// Writes an allowlist of baggage entries to the spans as `baggage.<key>` tags. `*` copies
+/// every entry.
+#[derive(Debug)]
+pub(crate) struct BaggageTagProcessor {
+    keys: Vec<String>,
+}
+
+impl BaggageTagProcessor {
+    pub(crate) fn new(keys: &[String]) -> Self {
+        Self {
+            keys: keys.to_vec(),
+        }
+    }
+
+    fn is_allowed(&self, key: &str) -> bool {
+        self.keys.iter().any(|it| it == "*" || it == key)
+    }
+}
+
+impl SpanProcessor for BaggageTagProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        use opentelemetry::trace::Span as _;
+
+        for (key, (value, _)) in cx.baggage() {
+            if self.is_allowed(key.as_str()) {
+                span.set_attribute(KeyValue::new(
+                    format!("{BAGGAGE_TAG_PREFIX}{}", key.as_str()),
+                    value.clone(),
+                ));
+            }
+        }
+    }
+
+    fn on_end(&self, _span: SpanData) {}
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::CapturingExporter;
+    use crate::propagator::CompositePropagator;
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::collections::HashMap;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn subscriber(keys: &[&str], exporter: CapturingExporter) -> impl tracing::Subscriber {
+        let keys: Vec<String> = keys.iter().map(|it| it.to_string()).collect();
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(BaggageTagProcessor::new(&keys))
+            .with_simple_exporter(exporter)
+            .build();
+        Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+    }
+
+    #[test]
+    fn test_set_get_and_remove() {
+        let subscriber = subscriber(&[], CapturingExporter::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            set("tenant.id", "acme");
+            set("user.tier", "gold");
+
+            let child = tracing::info_span!("child");
+            let _child = child.enter();
+            assert_eq!(get("tenant.id").as_deref(), Some("acme"));
+
+            remove("tenant.id");
+            assert_eq!(get("tenant.id"), None);
+            assert_eq!(get("user.tier").as_deref(), Some("gold"));
+        });
+    }
+
+    #[test]
+    fn test_injected_and_extracted() {
+        let subscriber = subscriber(&[], CapturingExporter::default());
+        let propagator = CompositePropagator::default();
+
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            set("tenant.id", "acme");
+
+            let request = tracing::info_span!("request");
+            let mut headers = HashMap::new();
+            propagator.inject_context(&request.context(), &mut headers);
+            headers
+        });
+        assert_eq!(headers["baggage"], "tenant.id=acme");
+
+        let cx = propagator.extract(&headers);
+        assert_eq!(
+            cx.baggage().get("tenant.id").map(|it| it.to_string()),
+            Some("acme".to_string())
+        );
+    }
+
+    #[test]
+    fn test_allowlisted_keys_copied_to_span_tags() {
+        let exporter = CapturingExporter::default();
+        let subscriber = subscriber(&["user.tier"], exporter.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            set("tenant.id", "acme");
+            set("user.tier", "gold");
+
+            let _child = tracing::info_span!("child").entered();
+        });
+
+        let spans = exporter.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        for span in spans.iter() {
+            let tags: HashMap<_, _> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.as_str(), kv.value.as_str()))
+                .collect();
+            assert_eq!(
+                tags.get("baggage.user.tier").map(|it| it.as_ref()),
+                Some("gold")
+            );
+            assert!(!tags.contains_key("baggage.tenant.id"));
+        }
+    }
+}
@@ -0,0 +1,1255 @@
+//! Programmatic configuration for [`init`](crate::init).
+//!
+//! [`DogdataConfig`] carries everything that used to be read straight from the process
+//! environment. It can be built entirely in code, or loaded from the usual `DD_*` variables
+//! with [`DogdataConfig::from_env`] and then adjusted with the `with_*` builder methods.
+//!
+//! Nothing in here ever writes to the environment, so it is safe to use from a
+//! multi-threaded runtime.
+
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use tracing_appender::non_blocking::DEFAULT_BUFFERED_LINES_LIMIT;
+
+use crate::formatter::LogKey;
+use crate::init::ModelMappings;
+use crate::propagator::PropagationStyle;
+use crate::redaction::Redactor;
+use crate::sampler::SamplingRule;
+
+const DEFAULT_AGENT_HOST: &str = "localhost";
+const DEFAULT_AGENT_PORT: u16 = 8126;
+const DEFAULT_DOGSTATSD_PORT: u16 = 8125;
+const DEFAULT_RATE_LIMIT: f64 = 100.0;
+const DEFAULT_LOG_DIRECTIVES: &str = "info";
+// `otel::setup` set to debug to log detected resources, configuration read and infered
+const DEFAULT_OTEL_LOG_LEVEL: &str = "debug";
+
+/// Output format of the log layer.
+///
+/// The human readable formats are those of `tracing_subscriber`, with `dd.trace_id` and
+/// `dd.span_id` appended by a [`CorrelatedFormat`](crate::formatter::CorrelatedFormat)
+/// when logged within a traced span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line, formatted by [`DatadogFormatter`](crate::formatter::DatadogFormatter).
+    Json,
+    /// The default human readable `tracing_subscriber` format.
+    Full,
+    /// Multiple lines per event, with the location and the spans on lines of their own.
+    Pretty,
+    /// A single line per event, with the fields of the spans inlined.
+    Compact,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(LogFormat::Json),
+            "full" | "text" => Ok(LogFormat::Full),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            other => Err(format!("unknown log format `{other}`")),
+        }
+    }
+}
+
+/// Destination of the log layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogOutput {
+    Stdout,
+    Stderr,
+    /// A file, rotated according to the [`LogRotation`].
+    File(PathBuf),
+}
+
+impl FromStr for LogOutput {
+    type Err = String;
+
+    /// Parses `stdout`, `stderr`, or anything else as a file path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Err("empty log output".to_string()),
+            it if it.eq_ignore_ascii_case("stdout") => Ok(LogOutput::Stdout),
+            it if it.eq_ignore_ascii_case("stderr") => Ok(LogOutput::Stderr),
+            path => Ok(LogOutput::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// When a [`LogOutput::File`] is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    /// Everything goes to the configured path.
+    Never,
+    /// Every hour, to `<path>.YYYY-MM-DD-HH`.
+    Hourly,
+    /// Every day, to `<path>.YYYY-MM-DD`.
+    Daily,
+    /// Once the file would exceed this many bytes, to `<path>.1`, shifting older files up.
+    Size(u64),
+}
+
+impl FromStr for LogRotation {
+    type Err = String;
+
+    /// Parses `never`, `hourly`, `daily`, or a size such as `10485760`, `512k` or `100mb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        match s.as_str() {
+            "never" => return Ok(LogRotation::Never),
+            "hourly" => return Ok(LogRotation::Hourly),
+            "daily" => return Ok(LogRotation::Daily),
+            _ => {}
+        }
+        let digits = s.trim_end_matches('b');
+        let (digits, unit) = match digits.char_indices().last() {
+            Some((i, 'k')) => (&digits[..i], 1 << 10),
+            Some((i, 'm')) => (&digits[..i], 1 << 20),
+            Some((i, 'g')) => (&digits[..i], 1 << 30),
+            _ => (digits, 1),
+        };
+        digits
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|it| it.checked_mul(unit))
+            .filter(|it| *it > 0)
+            .map(LogRotation::Size)
+            .ok_or_else(|| format!("unknown log rotation `{s}`"))
+    }
+}
+
+/// Backend the spans are exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceExporter {
+    /// The Datadog agent's native trace intake.
+    Datadog,
+    /// An OTLP endpoint, such as an OpenTelemetry Collector. Requires the `otlp` feature.
+    Otlp,
+    /// Both of the above.
+    Both,
+}
+
+impl TraceExporter {
+    pub(crate) fn datadog(self) -> bool {
+        matches!(self, TraceExporter::Datadog | TraceExporter::Both)
+    }
+
+    pub(crate) fn otlp(self) -> bool {
+        matches!(self, TraceExporter::Otlp | TraceExporter::Both)
+    }
+}
+
+impl FromStr for TraceExporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "datadog" => Ok(TraceExporter::Datadog),
+            "otlp" => Ok(TraceExporter::Otlp),
+            "both" => Ok(TraceExporter::Both),
+            other => Err(format!("unknown exporter `{other}`")),
+        }
+    }
+}
+
+/// Transport of the OTLP exporter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// OTLP over gRPC, usually on port 4317.
+    Grpc,
+    /// Binary protobuf over HTTP, usually on port 4318.
+    HttpProtobuf,
+}
+
+impl FromStr for OtlpProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http/protobuf" => Ok(OtlpProtocol::HttpProtobuf),
+            other => Err(format!("unsupported otlp protocol `{other}`")),
+        }
+    }
+}
+
+/// Output format of the console span exporter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleFormat {
+    /// A few human readable lines per span.
+    Pretty,
+    /// One JSON object per span and line.
+    Json,
+}
+
+impl FromStr for ConsoleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pretty" => Ok(ConsoleFormat::Pretty),
+            "json" => Ok(ConsoleFormat::Json),
+            other => Err(format!("unknown console format `{other}`")),
+        }
+    }
+}
+
+/// Configuration consumed by [`init`](crate::init) and
+/// [`build_tracer_provider`](crate::tracer::build_tracer_provider).
+///
+/// ```no_run
+/// use dogdata::config::{DogdataConfig, LogFormat};
+///
+/// let config = DogdataConfig::from_env()
+///     .with_service("billing-api")
+///     .with_env("staging")
+///     .with_log_format(LogFormat::Json);
+/// let (_guard, shutdown, _log_level) = dogdata::init(config).unwrap();
+/// # shutdown.shutdown();
+/// ```
+#[derive(Clone)]
+pub struct DogdataConfig {
+    pub(crate) enabled: bool,
+    pub(crate) service: Option<String>,
+    pub(crate) env: Option<String>,
+    pub(crate) version: Option<String>,
+    pub(crate) tags: Vec<(String, String)>,
+    pub(crate) agent_endpoint: String,
+    pub(crate) dogstatsd_endpoint: String,
+    pub(crate) runtime_metrics_enabled: bool,
+    pub(crate) panic_hook_enabled: bool,
+    pub(crate) error_events_enabled: bool,
+    pub(crate) exporter: TraceExporter,
+    pub(crate) otlp_endpoint: Option<String>,
+    pub(crate) otlp_protocol: OtlpProtocol,
+    pub(crate) console_exporter: Option<ConsoleFormat>,
+    pub(crate) sample_rate: Option<f64>,
+    pub(crate) sampling_rules: Vec<SamplingRule>,
+    pub(crate) rate_limit: f64,
+    pub(crate) trace_id_128_bit: bool,
+    pub(crate) stats_computation_enabled: bool,
+    pub(crate) propagation_style_extract: Vec<PropagationStyle>,
+    pub(crate) propagation_style_inject: Vec<PropagationStyle>,
+    pub(crate) baggage_tag_keys: Vec<String>,
+    pub(crate) log_directives: String,
+    pub(crate) otel_log_level: String,
+    pub(crate) log_format: Option<LogFormat>,
+    pub(crate) log_output: LogOutput,
+    pub(crate) log_rotation: LogRotation,
+    pub(crate) log_max_files: Option<usize>,
+    pub(crate) log_buffered_lines: usize,
+    pub(crate) log_lossy: bool,
+    pub(crate) log_keys: Vec<(LogKey, Option<String>)>,
+    pub(crate) log_span_fields: bool,
+    pub(crate) log_span_list: bool,
+    pub(crate) redactor: Option<Redactor>,
+    pub(crate) mappings: ModelMappings,
+}
+
+impl Default for DogdataConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            service: None,
+            env: None,
+            version: None,
+            tags: Vec::new(),
+            agent_endpoint: format!("http://{DEFAULT_AGENT_HOST}:{DEFAULT_AGENT_PORT}"),
+            dogstatsd_endpoint: format!("udp://{DEFAULT_AGENT_HOST}:{DEFAULT_DOGSTATSD_PORT}"),
+            runtime_metrics_enabled: false,
+            panic_hook_enabled: false,
+            error_events_enabled: false,
+            exporter: TraceExporter::Datadog,
+            otlp_endpoint: None,
+            otlp_protocol: OtlpProtocol::HttpProtobuf,
+            console_exporter: None,
+            sample_rate: None,
+            sampling_rules: Vec::new(),
+            rate_limit: DEFAULT_RATE_LIMIT,
+            trace_id_128_bit: false,
+            stats_computation_enabled: false,
+            propagation_style_extract: PropagationStyle::DEFAULT.to_vec(),
+            propagation_style_inject: PropagationStyle::DEFAULT.to_vec(),
+            baggage_tag_keys: Vec::new(),
+            log_directives: DEFAULT_LOG_DIRECTIVES.to_string(),
+            otel_log_level: DEFAULT_OTEL_LOG_LEVEL.to_string(),
+            log_format: None,
+            log_output: LogOutput::Stdout,
+            log_rotation: LogRotation::Never,
+            log_max_files: None,
+            log_buffered_lines: DEFAULT_BUFFERED_LINES_LIMIT,
+            log_lossy: true,
+            log_keys: Vec::new(),
+            log_span_fields: false,
+            log_span_list: false,
+            redactor: None,
+            mappings: ModelMappings::default(),
+        }
+    }
+}
+
+impl DogdataConfig {
+    /// Loads the configuration from the environment, falling back to the defaults for
+    /// anything that is not set.
+    ///
+    /// | env var          | default     |
+    /// |------------------|-------------|
+    /// | `DD_ENABLED`     | `false`     |
+    /// | `DD_SERVICE`     |             |
+    /// | `DD_ENV`         |             |
+    /// | `DD_VERSION`     |             |
+    /// | `DD_TAGS`        |             |
+    /// | `DD_TRACE_AGENT_URL` | `http://$DD_AGENT_HOST:$DD_AGENT_PORT` |
+    /// | `DD_AGENT_HOST`  | `localhost` |
+    /// | `DD_AGENT_PORT`  | `8126`      |
+    /// | `DD_DOGSTATSD_URL` | `udp://$DD_AGENT_HOST:$DD_DOGSTATSD_PORT` |
+    /// | `DD_DOGSTATSD_PORT` | `8125`   |
+    /// | `DD_RUNTIME_METRICS_ENABLED` | `false` |
+    /// | `DD_PANIC_HOOK_ENABLED` | `false` |
+    /// | `DD_TRACE_ERROR_EVENTS_ENABLED` | `false` |
+    /// | `DD_EXPORTER`    | `datadog`   |
+    /// | `OTEL_EXPORTER_OTLP_ENDPOINT` |  |
+    /// | `OTEL_EXPORTER_OTLP_PROTOCOL` | `http/protobuf` |
+    /// | `DD_TRACE_CONSOLE_EXPORTER` |  |
+    /// | `DD_TRACE_SAMPLE_RATE` |       |
+    /// | `DD_TRACE_SAMPLING_RULES` |    |
+    /// | `DD_TRACE_RATE_LIMIT` | `100`  |
+    /// | `DD_TRACE_128_BIT_TRACEID_GENERATION_ENABLED` | `false` |
+    /// | `DD_TRACE_STATS_COMPUTATION_ENABLED` | `false` |
+    /// | `DD_TRACE_PROPAGATION_STYLE` | `datadog,tracecontext,baggage` |
+    /// | `DD_TRACE_PROPAGATION_STYLE_EXTRACT` | `$DD_TRACE_PROPAGATION_STYLE` |
+    /// | `DD_TRACE_PROPAGATION_STYLE_INJECT` | `$DD_TRACE_PROPAGATION_STYLE` |
+    /// | `DD_TRACE_BAGGAGE_TAG_KEYS` |  |
+    /// | `DD_LOG_FORMAT`  | `json` when enabled, `full` otherwise |
+    /// | `DD_LOG_OUTPUT`  | `stdout`    |
+    /// | `DD_LOG_ROTATION` | `never`    |
+    /// | `DD_LOG_MAX_FILES` |           |
+    /// | `DD_LOG_BUFFERED_LINES` | `128000` |
+    /// | `DD_LOG_LOSSY`   | `true`      |
+    /// | `DD_LOG_KEYS`    |             |
+    /// | `DD_LOG_SPAN_FIELDS` | `false` |
+    /// | `DD_LOG_SPAN_LIST` | `false`   |
+    /// | `DD_REDACTION_ENABLED` | `false` |
+    /// | `DD_REDACTION_KEYS` |          |
+    /// | `DD_REDACTION_PATTERNS` |      |
+    /// | `RUST_LOG`       | `info`      |
+    /// | `OTEL_LOG_LEVEL` | `debug`     |
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    pub(crate) fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let defaults = Self::default();
+
+        let host = lookup("DD_AGENT_HOST").unwrap_or_else(|| DEFAULT_AGENT_HOST.to_string());
+        let port = lookup("DD_AGENT_PORT")
+            .and_then(|it| it.parse::<u16>().ok())
+            .unwrap_or(DEFAULT_AGENT_PORT);
+        let dogstatsd_port = lookup("DD_DOGSTATSD_PORT")
+            .and_then(|it| it.parse::<u16>().ok())
+            .unwrap_or(DEFAULT_DOGSTATSD_PORT);
+        let propagation_style = lookup("DD_TRACE_PROPAGATION_STYLE")
+            .map(|it| PropagationStyle::parse_list(&it))
+            .unwrap_or(defaults.propagation_style_extract.clone());
+
+        Self {
+            enabled: lookup("DD_ENABLED").map(|s| s == "true").unwrap_or(false),
+            service: lookup("DD_SERVICE"),
+            env: lookup("DD_ENV"),
+            version: lookup("DD_VERSION"),
+            tags: lookup("DD_TAGS")
+                .map(|it| parse_tags(&it))
+                .unwrap_or_default(),
+            agent_endpoint: lookup("DD_TRACE_AGENT_URL")
+                .unwrap_or_else(|| format!("http://{host}:{port}")),
+            dogstatsd_endpoint: lookup("DD_DOGSTATSD_URL")
+                .unwrap_or_else(|| format!("udp://{host}:{dogstatsd_port}")),
+            runtime_metrics_enabled: lookup("DD_RUNTIME_METRICS_ENABLED")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.runtime_metrics_enabled),
+            panic_hook_enabled: lookup("DD_PANIC_HOOK_ENABLED")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.panic_hook_enabled),
+            error_events_enabled: lookup("DD_TRACE_ERROR_EVENTS_ENABLED")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.error_events_enabled),
+            exporter: lookup("DD_EXPORTER")
+                .and_then(|it| it.parse().ok())
+                .unwrap_or(defaults.exporter),
+            otlp_endpoint: lookup("OTEL_EXPORTER_OTLP_ENDPOINT"),
+            otlp_protocol: lookup("OTEL_EXPORTER_OTLP_PROTOCOL")
+                .and_then(|it| it.parse().ok())
+                .unwrap_or(defaults.otlp_protocol),
+            console_exporter: lookup("DD_TRACE_CONSOLE_EXPORTER").and_then(|it| it.parse().ok()),
+            sample_rate: lookup("DD_TRACE_SAMPLE_RATE").and_then(|it| parse_rate(&it)),
+            sampling_rules: lookup("DD_TRACE_SAMPLING_RULES")
+                .and_then(|it| serde_json::from_str(&it).ok())
+                .unwrap_or_default(),
+            rate_limit: lookup("DD_TRACE_RATE_LIMIT")
+                .and_then(|it| it.parse::<f64>().ok())
+                .filter(|it| *it >= 0.0)
+                .unwrap_or(defaults.rate_limit),
+            trace_id_128_bit: lookup("DD_TRACE_128_BIT_TRACEID_GENERATION_ENABLED")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.trace_id_128_bit),
+            stats_computation_enabled: lookup("DD_TRACE_STATS_COMPUTATION_ENABLED")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.stats_computation_enabled),
+            propagation_style_extract: lookup("DD_TRACE_PROPAGATION_STYLE_EXTRACT")
+                .map(|it| PropagationStyle::parse_list(&it))
+                .unwrap_or(propagation_style.clone()),
+            propagation_style_inject: lookup("DD_TRACE_PROPAGATION_STYLE_INJECT")
+                .map(|it| PropagationStyle::parse_list(&it))
+                .unwrap_or(propagation_style),
+            baggage_tag_keys: lookup("DD_TRACE_BAGGAGE_TAG_KEYS")
+                .map(|it| parse_list(&it))
+                .unwrap_or(defaults.baggage_tag_keys),
+            log_directives: lookup("RUST_LOG").unwrap_or(defaults.log_directives),
+            otel_log_level: lookup("OTEL_LOG_LEVEL").unwrap_or(defaults.otel_log_level),
+            log_format: lookup("DD_LOG_FORMAT").and_then(|it| it.parse().ok()),
+            log_output: lookup("DD_LOG_OUTPUT")
+                .and_then(|it| it.parse().ok())
+                .unwrap_or(defaults.log_output),
+            log_rotation: lookup("DD_LOG_ROTATION")
+                .and_then(|it| it.parse().ok())
+                .unwrap_or(defaults.log_rotation),
+            log_max_files: lookup("DD_LOG_MAX_FILES")
+                .and_then(|it| it.parse::<usize>().ok())
+                .filter(|it| *it > 0),
+            log_buffered_lines: lookup("DD_LOG_BUFFERED_LINES")
+                .and_then(|it| it.parse::<usize>().ok())
+                .filter(|it| *it > 0)
+                .unwrap_or(defaults.log_buffered_lines),
+            log_lossy: lookup("DD_LOG_LOSSY")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.log_lossy),
+            log_keys: lookup("DD_LOG_KEYS")
+                .map(|it| parse_log_keys(&it))
+                .unwrap_or_default(),
+            log_span_fields: lookup("DD_LOG_SPAN_FIELDS")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.log_span_fields),
+            log_span_list: lookup("DD_LOG_SPAN_LIST")
+                .map(|s| s == "true")
+                .unwrap_or(defaults.log_span_list),
+            redactor: lookup("DD_REDACTION_ENABLED")
+                .filter(|s| s == "true")
+                .map(|_| {
+                    parse_redactor(
+                        lookup("DD_REDACTION_KEYS").as_deref(),
+                        lookup("DD_REDACTION_PATTERNS").as_deref(),
+                    )
+                }),
+            mappings: defaults.mappings,
+        }
+    }
+
+    /// Enables the Datadog exporter and trace correlation in logs.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Sets the Datadog service name. Required when the exporter is enabled.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Sets the deployment environment (`env` tag).
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.env = Some(env.into());
+        self
+    }
+
+    /// Sets the service version (`version` tag).
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Adds a tag that is attached to every exported span.
+    pub fn with_tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the Datadog agent endpoint, e.g. `http://localhost:8126` or
+    /// `unix:///var/run/datadog/apm.socket`.
+    pub fn with_agent_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.agent_endpoint = endpoint.into();
+        self
+    }
+
+    /// Sets the DogStatsD server the [`metrics`](crate::metrics) are sent to, as a
+    /// `udp://host:port` or `unix:///path/to/dsd.socket` url.
+    pub fn with_dogstatsd_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.dogstatsd_endpoint = endpoint.into();
+        self
+    }
+
+    /// Reports the metrics of the tokio runtime `init` is called from, see
+    /// [`RuntimeMetricsReporter`](crate::runtime::RuntimeMetricsReporter).
+    pub fn with_runtime_metrics(mut self, enabled: bool) -> Self {
+        self.runtime_metrics_enabled = enabled;
+        self
+    }
+
+    /// Installs the [panic hook](crate::panic) when the subscriber is installed by
+    /// [`init`](crate::init) or [`try_init`](crate::try_init).
+    pub fn with_panic_hook(mut self, enabled: bool) -> Self {
+        self.panic_hook_enabled = enabled;
+        self
+    }
+
+    /// Marks the span enclosing an `ERROR` event as errored, see
+    /// [`ErrorEventLayer`](crate::error::ErrorEventLayer).
+    pub fn with_error_events(mut self, enabled: bool) -> Self {
+        self.error_events_enabled = enabled;
+        self
+    }
+
+    /// Selects the backend the spans are exported to.
+    pub fn with_exporter(mut self, exporter: TraceExporter) -> Self {
+        self.exporter = exporter;
+        self
+    }
+
+    /// Sets the base URL of the OTLP endpoint, e.g. `http://otel-collector:4318`.
+    pub fn with_otlp_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.otlp_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Sets the transport of the OTLP exporter.
+    pub fn with_otlp_protocol(mut self, protocol: OtlpProtocol) -> Self {
+        self.otlp_protocol = protocol;
+        self
+    }
+
+    /// Prints finished spans to stdout. Without [`with_enabled`](Self::with_enabled), spans
+    /// are only printed, which allows seeing what would be sent to the agent without running
+    /// one.
+    pub fn with_console_exporter(mut self, format: ConsoleFormat) -> Self {
+        self.console_exporter = Some(format);
+        self
+    }
+
+    /// Keeps traces that no sampling rule matches at this rate, clamped between `0.0` and
+    /// `1.0`. `NaN` leaves the rate unset.
+    pub fn with_sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = (!rate.is_nan()).then(|| rate.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Appends a sampling rule. Rules are evaluated in order and the first match wins.
+    pub fn with_sampling_rule(mut self, rule: SamplingRule) -> Self {
+        self.sampling_rules.push(rule);
+        self
+    }
+
+    /// Sets the maximum number of traces per second kept by the sampling rules.
+    pub fn with_rate_limit(mut self, traces_per_second: f64) -> Self {
+        self.rate_limit = traces_per_second.max(0.0);
+        self
+    }
+
+    /// Generates 128-bit trace ids, prefixed with the start time as Datadog does, and logs
+    /// trace ids as 32 hex characters when their upper half is set.
+    pub fn with_128_bit_trace_ids(mut self, enabled: bool) -> Self {
+        self.trace_id_128_bit = enabled;
+        self
+    }
+
+    /// Computes the hits, errors and latency stats of every span, sampled or not, and sends
+    /// them to the agent alongside the traces.
+    pub fn with_stats_computation(mut self, enabled: bool) -> Self {
+        self.stats_computation_enabled = enabled;
+        self
+    }
+
+    /// Sets the header formats trace context is extracted from, in order of precedence.
+    pub fn with_propagation_style_extract(mut self, styles: Vec<PropagationStyle>) -> Self {
+        self.propagation_style_extract = styles;
+        self
+    }
+
+    /// Sets the header formats trace context is injected in.
+    pub fn with_propagation_style_inject(mut self, styles: Vec<PropagationStyle>) -> Self {
+        self.propagation_style_inject = styles;
+        self
+    }
+
+    /// Sets the baggage keys copied to the spans as `baggage.<key>` tags. `*` copies every
+    /// key, an empty list, the default, none.
+    pub fn with_baggage_tag_keys<T: Into<String>>(
+        mut self,
+        keys: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.baggage_tag_keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the [`EnvFilter`](tracing_subscriber::EnvFilter) directives, in `RUST_LOG` syntax.
+    pub fn with_log_directives<T: Into<String>>(mut self, directives: T) -> Self {
+        self.log_directives = directives.into();
+        self
+    }
+
+    /// Sets the level of the `otel` target.
+    pub fn with_otel_log_level<T: Into<String>>(mut self, level: T) -> Self {
+        self.otel_log_level = level.into();
+        self
+    }
+
+    /// Forces the log output format instead of deriving it from [`with_enabled`](Self::with_enabled).
+    pub fn with_log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = Some(format);
+        self
+    }
+
+    /// Sets where logs are written, stdout by default.
+    pub fn with_log_output(mut self, output: LogOutput) -> Self {
+        self.log_output = output;
+        self
+    }
+
+    /// Sets when a [`LogOutput::File`] is rotated.
+    pub fn with_log_rotation(mut self, rotation: LogRotation) -> Self {
+        self.log_rotation = rotation;
+        self
+    }
+
+    /// Sets how many log files are kept, counting the one being written. All of them are
+    /// kept by default, or when `max_files` is `0`.
+    pub fn with_log_max_files(mut self, max_files: usize) -> Self {
+        self.log_max_files = (max_files > 0).then_some(max_files);
+        self
+    }
+
+    /// Sets how many log lines can wait for the writer thread before the log layer drops
+    /// or blocks, see [`with_log_lossy`](Self::with_log_lossy). `0` is ignored, as it
+    /// would drop or block every line.
+    pub fn with_log_buffered_lines(mut self, limit: usize) -> Self {
+        if limit > 0 {
+            self.log_buffered_lines = limit;
+        }
+        self
+    }
+
+    /// Drops log lines when the buffer is full, rather than blocking the thread logging
+    /// them. Dropped lines are reported every 10 seconds as a warning and as the
+    /// `dogdata.log.dropped_lines` count.
+    pub fn with_log_lossy(mut self, lossy: bool) -> Self {
+        self.log_lossy = lossy;
+        self
+    }
+
+    /// Writes a standard attribute of the JSON logs under another key, see
+    /// [`DatadogFormatter::with_key`](crate::formatter::DatadogFormatter::with_key).
+    pub fn with_log_key<T: Into<String>>(mut self, key: LogKey, name: T) -> Self {
+        self.log_keys.push((key, Some(name.into())));
+        self
+    }
+
+    /// Leaves a standard attribute out of the JSON logs.
+    pub fn without_log_key(mut self, key: LogKey) -> Self {
+        self.log_keys.push((key, None));
+        self
+    }
+
+    /// Adds the fields of the enclosing spans to the JSON logs, see
+    /// [`DatadogFormatter::with_span_fields`](crate::formatter::DatadogFormatter::with_span_fields).
+    pub fn with_log_span_fields(mut self, enabled: bool) -> Self {
+        self.log_span_fields = enabled;
+        self
+    }
+
+    /// Adds the enclosing spans to the JSON logs as a `spans` list, see
+    /// [`DatadogFormatter::with_span_list`](crate::formatter::DatadogFormatter::with_span_list).
+    pub fn with_log_span_list(mut self, enabled: bool) -> Self {
+        self.log_span_list = enabled;
+        self
+    }
+
+    /// Redacts sensitive values from the JSON logs and from the spans before they are
+    /// exported, see [`Redactor`].
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Overrides the mapping of OpenTelemetry spans to Datadog's service, name and resource.
+    pub fn with_mappings(mut self, mappings: ModelMappings) -> Self {
+        self.mappings = mappings;
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn service(&self) -> Option<&str> {
+        self.service.as_deref()
+    }
+
+    pub fn env(&self) -> Option<&str> {
+        self.env.as_deref()
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Global tags, as parsed from `DD_TAGS` or added with [`with_tag`](Self::with_tag).
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    pub fn agent_endpoint(&self) -> &str {
+        &self.agent_endpoint
+    }
+
+    pub fn dogstatsd_endpoint(&self) -> &str {
+        &self.dogstatsd_endpoint
+    }
+
+    pub fn runtime_metrics_enabled(&self) -> bool {
+        self.runtime_metrics_enabled
+    }
+
+    pub fn panic_hook_enabled(&self) -> bool {
+        self.panic_hook_enabled
+    }
+
+    pub fn error_events_enabled(&self) -> bool {
+        self.error_events_enabled
+    }
+
+    pub fn exporter(&self) -> TraceExporter {
+        self.exporter
+    }
+
+    pub fn otlp_endpoint(&self) -> Option<&str> {
+        self.otlp_endpoint.as_deref()
+    }
+
+    pub fn otlp_protocol(&self) -> OtlpProtocol {
+        self.otlp_protocol
+    }
+
+    pub fn console_exporter(&self) -> Option<ConsoleFormat> {
+        self.console_exporter
+    }
+
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
+
+    pub fn sampling_rules(&self) -> &[SamplingRule] {
+        &self.sampling_rules
+    }
+
+    pub fn rate_limit(&self) -> f64 {
+        self.rate_limit
+    }
+
+    pub fn trace_id_128_bit(&self) -> bool {
+        self.trace_id_128_bit
+    }
+
+    pub fn stats_computation_enabled(&self) -> bool {
+        self.stats_computation_enabled
+    }
+
+    pub fn propagation_style_extract(&self) -> &[PropagationStyle] {
+        &self.propagation_style_extract
+    }
+
+    pub fn propagation_style_inject(&self) -> &[PropagationStyle] {
+        &self.propagation_style_inject
+    }
+
+    pub fn baggage_tag_keys(&self) -> &[String] {
+        &self.baggage_tag_keys
+    }
+
+    /// The log output format, which defaults to JSON when Datadog is enabled.
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or(if self.enabled {
+            LogFormat::Json
+        } else {
+            LogFormat::Full
+        })
+    }
+
+    pub fn log_output(&self) -> &LogOutput {
+        &self.log_output
+    }
+
+    pub fn log_rotation(&self) -> LogRotation {
+        self.log_rotation
+    }
+
+    pub fn log_max_files(&self) -> Option<usize> {
+        self.log_max_files
+    }
+
+    pub fn log_buffered_lines(&self) -> usize {
+        self.log_buffered_lines
+    }
+
+    pub fn log_lossy(&self) -> bool {
+        self.log_lossy
+    }
+
+    /// Renamed standard attributes of the JSON logs, `None` for the omitted ones.
+    pub fn log_keys(&self) -> &[(LogKey, Option<String>)] {
+        &self.log_keys
+    }
+
+    pub fn log_span_fields(&self) -> bool {
+        self.log_span_fields
+    }
+
+    pub fn log_span_list(&self) -> bool {
+        self.log_span_list
+    }
+
+    pub fn redactor(&self) -> Option<&Redactor> {
+        self.redactor.as_ref()
+    }
+
+    /// The full filter directives, including the `otel` target.
+    pub(crate) fn filter_directives(&self) -> String {
+        crate::log_level::filter_directives(&self.log_directives, &self.otel_log_level)
+    }
+}
+
+fn parse_rate(rate: &str) -> Option<f64> {
+    rate.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|it| (0.0..=1.0).contains(it))
+}
+
+/// Parses `key:name` pairs separated by commas, where an empty name omits the key. Unknown
+/// keys are skipped.
+fn parse_log_keys(keys: &str) -> Vec<(LogKey, Option<String>)> {
+    parse_list(keys)
+        .iter()
+        .filter_map(|it| {
+            let (key, name) = it.split_once(':').unwrap_or((it, ""));
+            let name = name.trim();
+            let key = key.parse().ok()?;
+            Some((key, (!name.is_empty()).then(|| name.to_string())))
+        })
+        .collect()
+}
+
+/// Builds the default redactor, extended with a comma separated list of keys and a JSON
+/// array of patterns. Invalid patterns are skipped.
+fn parse_redactor(keys: Option<&str>, patterns: Option<&str>) -> Redactor {
+    let mut redactor = Redactor::new().with_defaults();
+    for key in keys.map(parse_list).unwrap_or_default() {
+        redactor = redactor.with_key(key);
+    }
+    let patterns: Vec<String> = patterns
+        .and_then(|it| serde_json::from_str(it).ok())
+        .unwrap_or_default();
+    for pattern in patterns {
+        if let Ok(extended) = redactor.clone().with_pattern(&pattern) {
+            redactor = extended;
+        }
+    }
+    redactor
+}
+
+/// Parses a comma separated list, skipping empty entries.
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses `DD_TAGS`, a list of `key:value` pairs separated by commas or spaces.
+fn parse_tags(tags: &str) -> Vec<(String, String)> {
+    tags.split([',', ' '])
+        .filter_map(|tag| {
+            let (key, value) = tag.trim().split_once(':').unwrap_or((tag.trim(), ""));
+            (!key.is_empty()).then(|| (key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        move |key| vars.get(key).map(|it| it.to_string())
+    }
+
+    #[test]
+    fn test_defaults_without_env() {
+        let config = DogdataConfig::from_lookup(|_| None);
+
+        assert!(!config.enabled());
+        assert_eq!(config.service(), None);
+        assert_eq!(config.agent_endpoint(), "http://localhost:8126");
+        assert_eq!(config.dogstatsd_endpoint(), "udp://localhost:8125");
+        assert!(!config.runtime_metrics_enabled());
+        assert!(!config.panic_hook_enabled());
+        assert!(!config.error_events_enabled());
+        assert!(!config.stats_computation_enabled());
+        assert_eq!(config.filter_directives(), "info,otel=debug");
+        assert_eq!(config.log_format(), LogFormat::Full);
+        assert_eq!(config.log_output(), &LogOutput::Stdout);
+        assert_eq!(config.log_rotation(), LogRotation::Never);
+        assert_eq!(config.log_max_files(), None);
+        assert_eq!(config.log_buffered_lines(), 128_000);
+        assert!(config.log_lossy());
+        assert!(config.log_keys().is_empty());
+        assert!(!config.log_span_fields());
+        assert!(!config.log_span_list());
+        assert!(config.redactor().is_none());
+        assert!(!config.trace_id_128_bit());
+        assert_eq!(
+            config.propagation_style_extract(),
+            PropagationStyle::DEFAULT
+        );
+        assert_eq!(config.propagation_style_inject(), PropagationStyle::DEFAULT);
+        assert!(config.baggage_tag_keys().is_empty());
+        assert_eq!(config.exporter(), TraceExporter::Datadog);
+        assert_eq!(config.otlp_protocol(), OtlpProtocol::HttpProtobuf);
+    }
+
+    #[test]
+    fn test_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_ENABLED", "true"),
+            ("DD_SERVICE", "billing"),
+            ("DD_ENV", "prod"),
+            ("DD_VERSION", "1.2.3"),
+            ("DD_AGENT_HOST", "agent"),
+            ("DD_AGENT_PORT", "9126"),
+            ("RUST_LOG", "warn,billing=debug"),
+            ("OTEL_LOG_LEVEL", "error"),
+            ("DD_TRACE_128_BIT_TRACEID_GENERATION_ENABLED", "true"),
+            ("DD_RUNTIME_METRICS_ENABLED", "true"),
+            ("DD_PANIC_HOOK_ENABLED", "true"),
+            ("DD_TRACE_ERROR_EVENTS_ENABLED", "true"),
+            ("DD_TRACE_STATS_COMPUTATION_ENABLED", "true"),
+        ]));
+
+        assert!(config.enabled());
+        assert_eq!(config.service(), Some("billing"));
+        assert_eq!(config.env(), Some("prod"));
+        assert_eq!(config.version(), Some("1.2.3"));
+        assert!(config.tags().is_empty());
+        assert_eq!(config.agent_endpoint(), "http://agent:9126");
+        assert_eq!(config.dogstatsd_endpoint(), "udp://agent:8125");
+        assert_eq!(config.filter_directives(), "warn,billing=debug,otel=error");
+        assert_eq!(config.log_format(), LogFormat::Json);
+        assert!(config.trace_id_128_bit());
+        assert!(config.runtime_metrics_enabled());
+        assert!(config.panic_hook_enabled());
+        assert!(config.error_events_enabled());
+        assert!(config.stats_computation_enabled());
+    }
+
+    #[test]
+    fn test_exporter_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_EXPORTER", "Both"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4317"),
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc"),
+        ]));
+
+        assert_eq!(config.exporter(), TraceExporter::Both);
+        assert!(config.exporter().datadog() && config.exporter().otlp());
+        assert_eq!(config.otlp_endpoint(), Some("http://collector:4317"));
+        assert_eq!(config.otlp_protocol(), OtlpProtocol::Grpc);
+
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_EXPORTER", "zipkin"),
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/json"),
+        ]));
+        assert_eq!(config.exporter(), TraceExporter::Datadog);
+        assert_eq!(config.otlp_protocol(), OtlpProtocol::HttpProtobuf);
+    }
+
+    #[test]
+    fn test_console_exporter_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[("DD_TRACE_CONSOLE_EXPORTER", "JSON")]));
+        assert_eq!(config.console_exporter(), Some(ConsoleFormat::Json));
+
+        let config = DogdataConfig::from_lookup(lookup(&[("DD_TRACE_CONSOLE_EXPORTER", "yaml")]));
+        assert_eq!(config.console_exporter(), None);
+    }
+
+    #[test]
+    fn test_dogstatsd_endpoint_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_AGENT_HOST", "agent"),
+            ("DD_DOGSTATSD_PORT", "9125"),
+        ]));
+        assert_eq!(config.dogstatsd_endpoint(), "udp://agent:9125");
+
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_DOGSTATSD_URL", "unix:///var/run/datadog/dsd.socket"),
+            ("DD_AGENT_HOST", "agent"),
+        ]));
+        assert_eq!(
+            config.dogstatsd_endpoint(),
+            "unix:///var/run/datadog/dsd.socket"
+        );
+    }
+
+    #[test]
+    fn test_agent_url_takes_precedence_over_host_and_port() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_TRACE_AGENT_URL", "unix:///var/run/datadog/apm.socket"),
+            ("DD_AGENT_HOST", "agent"),
+        ]));
+
+        assert_eq!(
+            config.agent_endpoint(),
+            "unix:///var/run/datadog/apm.socket"
+        );
+    }
+
+    #[test]
+    fn test_sampling_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_TRACE_SAMPLE_RATE", "0.1"),
+            (
+                "DD_TRACE_SAMPLING_RULES",
+                r#"[{"service": "billing", "resource": "GET /health", "sample_rate": 0}]"#,
+            ),
+            ("DD_TRACE_RATE_LIMIT", "50"),
+        ]));
+
+        assert_eq!(config.sample_rate(), Some(0.1));
+        assert_eq!(
+            config.sampling_rules(),
+            &[SamplingRule::new(0.0)
+                .with_service("billing")
+                .with_resource("GET /health")]
+        );
+        assert_eq!(config.rate_limit(), 50.0);
+    }
+
+    #[test]
+    fn test_sample_rate_clamped() {
+        let config = DogdataConfig::default().with_sample_rate(2.0);
+        assert_eq!(config.sample_rate(), Some(1.0));
+
+        let config = config.with_sample_rate(-0.5);
+        assert_eq!(config.sample_rate(), Some(0.0));
+
+        let config = config.with_sample_rate(f64::NAN);
+        assert_eq!(config.sample_rate(), None);
+    }
+
+    #[test]
+    fn test_propagation_styles_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_TRACE_PROPAGATION_STYLE", "b3multi,datadog"),
+            ("DD_TRACE_PROPAGATION_STYLE_INJECT", "tracecontext"),
+        ]));
+
+        assert_eq!(
+            config.propagation_style_extract(),
+            &[PropagationStyle::B3Multi, PropagationStyle::Datadog]
+        );
+        assert_eq!(
+            config.propagation_style_inject(),
+            &[PropagationStyle::TraceContext]
+        );
+
+        let config =
+            DogdataConfig::from_lookup(lookup(&[("DD_TRACE_PROPAGATION_STYLE_EXTRACT", "none")]));
+        assert!(config.propagation_style_extract().is_empty());
+        assert_eq!(config.propagation_style_inject(), PropagationStyle::DEFAULT);
+    }
+
+    #[test]
+    fn test_baggage_tag_keys_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[(
+            "DD_TRACE_BAGGAGE_TAG_KEYS",
+            "tenant.id, user.tier,",
+        )]));
+        assert_eq!(config.baggage_tag_keys(), &["tenant.id", "user.tier"]);
+
+        let config = DogdataConfig::from_lookup(lookup(&[("DD_TRACE_BAGGAGE_TAG_KEYS", "")]));
+        assert!(config.baggage_tag_keys().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_sampling_ignored() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_TRACE_SAMPLE_RATE", "2"),
+            ("DD_TRACE_SAMPLING_RULES", "not json"),
+            ("DD_TRACE_RATE_LIMIT", "-1"),
+        ]));
+
+        assert_eq!(config.sample_rate(), None);
+        assert!(config.sampling_rules().is_empty());
+        assert_eq!(config.rate_limit(), DEFAULT_RATE_LIMIT);
+    }
+
+    #[test]
+    fn test_tags_separated_by_commas_or_spaces() {
+        let tags = parse_tags("team:payments, region:eu-west-1 url:http://x  standalone");
+
+        assert_eq!(
+            tags,
+            vec![
+                ("team".to_string(), "payments".to_string()),
+                ("region".to_string(), "eu-west-1".to_string()),
+                ("url".to_string(), "http://x".to_string()),
+                ("standalone".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_overrides_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_ENABLED", "true"),
+            ("DD_SERVICE", "billing"),
+            ("DD_LOG_FORMAT", "json"),
+        ]))
+        .with_service("payments")
+        .with_log_format(LogFormat::Full);
+
+        assert_eq!(config.service(), Some("payments"));
+        assert_eq!(config.log_format(), LogFormat::Full);
+    }
+
+    #[test]
+    fn test_log_output_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_LOG_OUTPUT", "/var/log/billing/app.log"),
+            ("DD_LOG_ROTATION", "100MB"),
+            ("DD_LOG_MAX_FILES", "5"),
+            ("DD_LOG_BUFFERED_LINES", "1024"),
+            ("DD_LOG_LOSSY", "false"),
+            ("DD_LOG_SPAN_FIELDS", "true"),
+            ("DD_LOG_SPAN_LIST", "true"),
+        ]));
+
+        assert_eq!(
+            config.log_output(),
+            &LogOutput::File(PathBuf::from("/var/log/billing/app.log"))
+        );
+        assert_eq!(config.log_rotation(), LogRotation::Size(100 << 20));
+        assert_eq!(config.log_max_files(), Some(5));
+        assert_eq!(config.log_buffered_lines(), 1024);
+        assert!(!config.log_lossy());
+        assert!(config.log_span_fields());
+        assert!(config.log_span_list());
+
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_LOG_OUTPUT", "STDERR"),
+            ("DD_LOG_ROTATION", "weekly"),
+            ("DD_LOG_MAX_FILES", "0"),
+        ]));
+
+        assert_eq!(config.log_output(), &LogOutput::Stderr);
+        assert_eq!(config.log_rotation(), LogRotation::Never);
+        assert_eq!(config.log_max_files(), None);
+    }
+
+    #[test]
+    fn test_log_max_files_zero_keeps_all() {
+        let config = DogdataConfig::default().with_log_max_files(5);
+        assert_eq!(config.log_max_files(), Some(5));
+
+        let config = config.with_log_max_files(0);
+        assert_eq!(config.log_max_files(), None);
+    }
+
+    #[test]
+    fn test_log_buffered_lines_zero_ignored() {
+        let config = DogdataConfig::default().with_log_buffered_lines(1024);
+        assert_eq!(config.log_buffered_lines(), 1024);
+
+        let config = config.with_log_buffered_lines(0);
+        assert_eq!(config.log_buffered_lines(), 1024);
+    }
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert_eq!("text".parse(), Ok(LogFormat::Full));
+        assert_eq!("pretty".parse(), Ok(LogFormat::Pretty));
+        assert_eq!(" compact ".parse(), Ok(LogFormat::Compact));
+        assert!("logfmt".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_log_rotation_parse() {
+        assert_eq!("Hourly".parse(), Ok(LogRotation::Hourly));
+        assert_eq!("daily".parse(), Ok(LogRotation::Daily));
+        assert_eq!("1048576".parse(), Ok(LogRotation::Size(1 << 20)));
+        assert_eq!("512k".parse(), Ok(LogRotation::Size(512 << 10)));
+        assert_eq!("2 GB".parse(), Ok(LogRotation::Size(2 << 30)));
+        assert!("0".parse::<LogRotation>().is_err());
+        assert!("mb".parse::<LogRotation>().is_err());
+    }
+
+    #[test]
+    fn test_log_keys_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[(
+            "DD_LOG_KEYS",
+            "status:level, logger.thread_name:, logger.name : target, unknown:x",
+        )]));
+
+        assert_eq!(
+            config.log_keys(),
+            &[
+                (LogKey::Status, Some("level".to_string())),
+                (LogKey::ThreadName, None),
+                (LogKey::LoggerName, Some("target".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redactor_loaded_from_env() {
+        let config = DogdataConfig::from_lookup(lookup(&[
+            ("DD_REDACTION_ENABLED", "true"),
+            ("DD_REDACTION_KEYS", "ssn, iban"),
+            (
+                "DD_REDACTION_PATTERNS",
+                r#"["sess_[a-z0-9]+", "(unclosed"]"#,
+            ),
+        ]));
+
+        let redactor = config.redactor().unwrap();
+        assert!(redactor.redacts_key("password"));
+        assert!(redactor.redacts_key("user.iban"));
+        assert_eq!(
+            redactor.redact("resumed sess_1a2b for jane@example.com"),
+            "resumed [REDACTED] for [REDACTED]"
+        );
+
+        let config = DogdataConfig::from_lookup(lookup(&[("DD_REDACTION_KEYS", "ssn")]));
+        assert!(config.redactor().is_none());
+    }
+}
@@ -0,0 +1,314 @@
+//! Datadog error tracking attributes for [`std::error::Error`] values.
+//!
+//! Error Tracking groups failed spans by their `error.type`, `error.message` and
+//! `error.stack` tags. [`record_error`] fills them in, along with the span status:
+//!
+//! ```
+//! use tracing::field::Empty;
+//!
+//! let span = tracing::info_span!(
+//!     "charge",
+//!     otel.status_code = Empty,
+//!     error.type = Empty,
+//!     error.message = Empty,
+//!     error.stack = Empty,
+//! );
+//! if let Err(err) = "forty-two".parse::<u32>() {
+//!     dogdata::error::record_error(&span, &err);
+//! }
+//! ```
+//!
+//! Like any [`tracing`] field, they have to be declared when the span is created, as the
+//! spans of the axum, reqwest and sqlx integrations do.
+//!
+//! `error.type` is the name of the static type of the error, see [`error_type_name`]. A
+//! boxed `dyn Error` does not tell its concrete type, so `error.type` is left empty for it
+//! unless given to [`record_error_with_type`].
+//!
+//! These helpers live in the `dogdata-error` crate, which the reqwest and sqlx integrations
+//! depend on, and are re-exported here.
+//!
+//! Alternatively, the [`ErrorEventLayer`] marks the enclosing span as errored for every
+//! `ERROR` event, without touching the call sites.
+
+use std::backtrace::Backtrace;
+use std::error::Error;
+use std::fmt;
+
+use opentelemetry::KeyValue;
+use opentelemetry::trace::Status;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+pub(crate) use dogdata_error::error_stack;
+pub use dogdata_error::{
+    ERROR_MESSAGE, ERROR_STACK, ERROR_TYPE, error_type_name, record_error, record_error_with_type,
+};
+
+/// Marks the span enclosing an `ERROR` event as errored.
+///
+/// The message of the event becomes `error.message`, and its `error.type` field, or else
+/// its `error` field, `error.type`. An `error` recorded as a [`std::error::Error`] fills in
+/// `error.stack` instead: its type is erased, so `error.type` has to be given explicitly,
+/// as for [`record_error_with_type`]:
+///
+/// ```
+/// # let err = std::io::Error::other("timed out");
+/// tracing::error!(
+///     error.type = "std::io::Error",
+///     error = &err as &dyn std::error::Error,
+///     "failed to charge the card",
+/// );
+/// ```
+///
+/// Installed by [`init`](crate::init) when `DD_TRACE_ERROR_EVENTS_ENABLED=true`. It must
+/// come after the OpenTelemetry layer, which already marks these spans as errored but
+/// without a message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ErrorEventLayer;
+
+impl ErrorEventLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S> Layer<S> for ErrorEventLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+
+        let mut visitor = ErrorEventVisitor::default();
+        event.record(&mut visitor);
+        let error_type = visitor.error_type.or(visitor.error);
+        let message = visitor
+            .message
+            .or(visitor.error_message)
+            .or_else(|| error_type.clone())
+            .unwrap_or_default();
+
+        let mut extensions = span.extensions_mut();
+        let Some(data) = extensions.get_mut::<OtelData>() else {
+            return;
+        };
+        data.builder.status = Status::error(message.clone());
+        let attributes = data.builder.attributes.get_or_insert_with(Vec::new);
+        // the last error of the span wins
+        attributes
+            .retain(|kv| !matches!(kv.key.as_str(), ERROR_TYPE | ERROR_MESSAGE | ERROR_STACK));
+        attributes.push(KeyValue::new(ERROR_MESSAGE, message));
+        if let Some(error_type) = error_type {
+            attributes.push(KeyValue::new(ERROR_TYPE, error_type));
+        }
+        if let Some(stack) = visitor.stack {
+            attributes.push(KeyValue::new(ERROR_STACK, stack));
+        }
+    }
+}
+
+#[derive(Default)]
+struct ErrorEventVisitor {
+    message: Option<String>,
+    /// The `error.type` field.
+    error_type: Option<String>,
+    /// The `error` field, when not recorded as an [`Error`].
+    error: Option<String>,
+    /// The message of the `error` field recorded as an [`Error`].
+    error_message: Option<String>,
+    stack: Option<String>,
+}
+
+impl Visit for ErrorEventVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = Some(value.to_string()),
+            ERROR_TYPE => self.error_type = Some(value.to_string()),
+            "error" => self.error = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+        if field.name() == "error" {
+            self.error_message = Some(value.to_string());
+            self.stack = Some(error_stack(value, &Backtrace::disabled()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = Some(format!("{value:?}")),
+            ERROR_TYPE => self.error_type = Some(format!("{value:?}")),
+            "error" => self.error = Some(format!("{value:?}")),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::{CapturingExporter, attribute};
+    use opentelemetry::Value;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::{SdkTracerProvider, SpanData};
+    use tracing_subscriber::Registry;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn export_span(emit: impl FnOnce()) -> SpanData {
+        let exporter = CapturingExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(ErrorEventLayer::new());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "charge",
+                otel.status_code = tracing::field::Empty,
+                error.type = tracing::field::Empty,
+                error.message = tracing::field::Empty,
+                error.stack = tracing::field::Empty,
+            )
+            .in_scope(emit);
+        });
+
+        exporter.0.lock().unwrap().pop().unwrap()
+    }
+
+    #[derive(Debug)]
+    struct ChargeError(std::io::Error);
+
+    impl fmt::Display for ChargeError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("failed to charge the card")
+        }
+    }
+
+    impl Error for ChargeError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn record_span(record: impl FnOnce(&tracing::Span)) -> SpanData {
+        export_span(|| record(&tracing::Span::current()))
+    }
+
+    #[test]
+    fn test_record_error_type() {
+        let error = ChargeError(std::io::Error::other("timed out"));
+        let span = record_span(|span| record_error(span, &error));
+        assert_eq!(
+            attribute(&span, ERROR_TYPE),
+            Some(&Value::from(std::any::type_name::<ChargeError>()))
+        );
+        assert_eq!(
+            attribute(&span, ERROR_MESSAGE),
+            Some(&Value::from("failed to charge the card"))
+        );
+
+        // the concrete type of a boxed error is unknown
+        let boxed: Box<dyn Error + Send + Sync> = Box::new(error);
+        let span = record_span(|span| record_error(span, &*boxed));
+        assert_eq!(attribute(&span, ERROR_TYPE), None);
+        assert_eq!(
+            attribute(&span, ERROR_MESSAGE),
+            Some(&Value::from("failed to charge the card"))
+        );
+
+        let span = record_span(|span| record_error_with_type(span, &*boxed, "ChargeError"));
+        assert_eq!(
+            attribute(&span, ERROR_TYPE),
+            Some(&Value::from("ChargeError"))
+        );
+    }
+
+    #[test]
+    fn test_error_event_marks_span() {
+        let span = export_span(|| {
+            tracing::error!(error = "CardDeclined", "failed to charge the card");
+        });
+
+        assert_eq!(span.status, Status::error("failed to charge the card"));
+        assert_eq!(
+            attribute(&span, ERROR_MESSAGE),
+            Some(&Value::from("failed to charge the card"))
+        );
+        assert_eq!(
+            attribute(&span, ERROR_TYPE),
+            Some(&Value::from("CardDeclined"))
+        );
+    }
+
+    #[test]
+    fn test_error_event_records_error_chain() {
+        let span = export_span(|| {
+            let err = ChargeError(std::io::Error::other("timed out"));
+            tracing::error!(error = &err as &dyn Error);
+        });
+
+        // the type of a `dyn Error` is unknown
+        assert_eq!(attribute(&span, ERROR_TYPE), None);
+        assert_eq!(
+            attribute(&span, ERROR_MESSAGE),
+            Some(&Value::from("failed to charge the card"))
+        );
+        assert_eq!(
+            attribute(&span, ERROR_STACK),
+            Some(&Value::from(
+                "failed to charge the card\n\nCaused by:\n    0: timed out"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_error_event_with_explicit_type() {
+        let span = export_span(|| {
+            let err = ChargeError(std::io::Error::other("timed out"));
+            tracing::error!(
+                error.type = "ChargeError",
+                error = &err as &dyn Error,
+                "charge failed"
+            );
+        });
+
+        assert_eq!(
+            attribute(&span, ERROR_TYPE),
+            Some(&Value::from("ChargeError"))
+        );
+        assert_eq!(
+            attribute(&span, ERROR_MESSAGE),
+            Some(&Value::from("charge failed"))
+        );
+        assert_eq!(
+            attribute(&span, ERROR_STACK),
+            Some(&Value::from(
+                "failed to charge the card\n\nCaused by:\n    0: timed out"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_warn_event_leaves_span_unset() {
+        let span = export_span(|| {
+            tracing::warn!(error = "CardDeclined", "retrying the charge");
+        });
+
+        assert_eq!(span.status, Status::Unset);
+        assert_eq!(attribute(&span, ERROR_MESSAGE), None);
+    }
+}
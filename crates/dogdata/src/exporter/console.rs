@@ -0,0 +1,303 @@
+//! Span exporter printing to stdout, for local development without an agent.
+//!
+//! Each finished span is printed as the Datadog exporter would send it: the service, name
+//! and resource come from the [`Mapping`], and the attributes are split into meta and
+//! metrics the same way.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use futures_util::future::BoxFuture;
+use opentelemetry::trace::Status;
+use opentelemetry_datadog::ModelConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+use serde::Serialize;
+
+use super::{Mapping, UnifiedTags, v05};
+use crate::config::ConsoleFormat;
+use crate::propagator::{TRACE_ID_HIGH_TAG, trace_id_high};
+use crate::sampler::{SAMPLING_PRIORITY_KEY, sampling_priority};
+
+type SharedWriter = Arc<Mutex<dyn Write + Send>>;
+
+/// A span as the agent would receive it.
+#[derive(Debug, Serialize)]
+struct ConsoleSpan<'a> {
+    service: &'a str,
+    name: &'a str,
+    resource: &'a str,
+    #[serde(rename = "type")]
+    span_type: String,
+    trace_id: u64,
+    span_id: u64,
+    parent_id: u64,
+    start: u64,
+    duration: u64,
+    error: i32,
+    meta: BTreeMap<String, String>,
+    metrics: BTreeMap<String, f64>,
+}
+
+impl<'a> ConsoleSpan<'a> {
+    fn new(
+        span: &'a SpanData,
+        model_config: &'a ModelConfig,
+        mapping: &'a Mapping,
+        unified_tags: &UnifiedTags,
+        resource: Option<&Resource>,
+    ) -> Self {
+        let mut meta = BTreeMap::new();
+        let mut metrics = BTreeMap::new();
+
+        if let Some(resource) = resource {
+            for (key, value) in resource.iter() {
+                meta.insert(key.to_string(), value.to_string());
+            }
+        }
+        if let Some(env) = &unified_tags.env {
+            meta.insert("env".to_string(), env.clone());
+        }
+        if let Some(version) = &unified_tags.version {
+            meta.insert("version".to_string(), version.clone());
+        }
+        for kv in span.attributes.iter() {
+            match v05::as_metric(kv.key.as_str(), &kv.value) {
+                Some(value) => {
+                    metrics.insert(kv.key.to_string(), value);
+                }
+                None => {
+                    meta.insert(kv.key.to_string(), kv.value.to_string());
+                }
+            }
+        }
+        if let Some(high) = trace_id_high(span.span_context.trace_id()) {
+            meta.insert(TRACE_ID_HIGH_TAG.to_string(), format!("{high:016x}"));
+        }
+        let priority = sampling_priority(span.span_context.trace_state())
+            .unwrap_or(if span.span_context.is_sampled() { 1 } else { 0 });
+        metrics.insert(SAMPLING_PRIORITY_KEY.to_string(), priority as f64);
+
+        Self {
+            service: (mapping.service_name)(span, model_config),
+            name: (mapping.name)(span, model_config),
+            resource: (mapping.resource)(span, model_config),
+            span_type: meta.get("span.type").cloned().unwrap_or_default(),
+            trace_id: u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
+            span_id: u64::from_be_bytes(span.span_context.span_id().to_bytes()),
+            parent_id: u64::from_be_bytes(span.parent_span_id.to_bytes()),
+            start: span
+                .start_time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            duration: span
+                .end_time
+                .duration_since(span.start_time)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            error: match span.status {
+                Status::Error { .. } => 1,
+                _ => 0,
+            },
+            meta,
+            metrics,
+        }
+    }
+
+    fn write_pretty(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{} {} {:?} type={:?} duration={:?}{}",
+            self.service,
+            self.name,
+            self.resource,
+            self.span_type,
+            Duration::from_nanos(self.duration),
+            if self.error == 1 { " error" } else { "" },
+        )?;
+        writeln!(
+            writer,
+            "    trace_id={} span_id={} parent_id={}",
+            self.trace_id, self.span_id, self.parent_id
+        )?;
+        for (key, value) in &self.meta {
+            writeln!(writer, "    {key}={value:?}")?;
+        }
+        for (key, value) in &self.metrics {
+            writeln!(writer, "    {key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints finished spans to stdout, in the shape they would be sent to the agent.
+pub(crate) struct ConsoleExporter {
+    format: ConsoleFormat,
+    writer: SharedWriter,
+    model_config: ModelConfig,
+    mapping: Mapping,
+    unified_tags: UnifiedTags,
+    resource: Option<Resource>,
+}
+
+impl ConsoleExporter {
+    pub(crate) fn new(
+        format: ConsoleFormat,
+        service_name: &str,
+        mapping: Mapping,
+        unified_tags: UnifiedTags,
+    ) -> Self {
+        Self::with_writer(
+            format,
+            Arc::new(Mutex::new(io::stdout())),
+            service_name,
+            mapping,
+            unified_tags,
+        )
+    }
+
+    fn with_writer(
+        format: ConsoleFormat,
+        writer: SharedWriter,
+        service_name: &str,
+        mapping: Mapping,
+        unified_tags: UnifiedTags,
+    ) -> Self {
+        let mut model_config = ModelConfig::default();
+        model_config.service_name = service_name.to_string();
+
+        Self {
+            format,
+            writer,
+            model_config,
+            mapping,
+            unified_tags,
+            resource: None,
+        }
+    }
+
+    fn write(&self, batch: &[SpanData]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        for span in batch {
+            let span = ConsoleSpan::new(
+                span,
+                &self.model_config,
+                &self.mapping,
+                &self.unified_tags,
+                self.resource.as_ref(),
+            );
+            match self.format {
+                ConsoleFormat::Pretty => span.write_pretty(&mut *writer)?,
+                ConsoleFormat::Json => {
+                    serde_json::to_writer(&mut *writer, &span)?;
+                    writeln!(writer)?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+impl Debug for ConsoleExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsoleExporter")
+            .field("format", &self.format)
+            .field("model_config", &self.model_config)
+            .field("unified_tags", &self.unified_tags)
+            .finish()
+    }
+}
+
+impl SpanExporter for ConsoleExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, OTelSdkResult> {
+        let result = self
+            .write(&batch)
+            .map_err(|err| OTelSdkError::InternalFailure(format!("failed to print spans: {err}")));
+        Box::pin(std::future::ready(result))
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = Some(resource.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::get_span_with;
+    use crate::exporter::v05::tests::default_mapping;
+    use crate::sampler::RULE_RATE_KEY;
+    use opentelemetry::KeyValue;
+    use opentelemetry::trace::TraceState;
+
+    fn export(format: ConsoleFormat, span: SpanData) -> String {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut exporter = ConsoleExporter::with_writer(
+            format,
+            buffer.clone(),
+            "billing",
+            default_mapping(),
+            UnifiedTags {
+                env: Some("dev".to_string()),
+                version: None,
+            },
+        );
+
+        futures_util::FutureExt::now_or_never(exporter.export(vec![span]))
+            .unwrap()
+            .unwrap();
+        String::from_utf8(buffer.lock().unwrap().clone()).unwrap()
+    }
+
+    fn span() -> SpanData {
+        get_span_with(
+            7,
+            3,
+            5,
+            vec![
+                KeyValue::new("span.type", "web"),
+                KeyValue::new("http.route", "/invoices"),
+                KeyValue::new(RULE_RATE_KEY, 0.5),
+            ],
+            TraceState::NONE,
+        )
+    }
+
+    #[test]
+    fn test_json_output() {
+        let output = export(ConsoleFormat::Json, span());
+        let span: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(span["service"], "billing");
+        assert_eq!(span["name"], "component");
+        assert_eq!(span["resource"], "resource");
+        assert_eq!(span["type"], "web");
+        assert_eq!(span["trace_id"], 7);
+        assert_eq!(span["span_id"], 5);
+        assert_eq!(span["parent_id"], 3);
+        assert_eq!(span["duration"], 1_000_000_000u64);
+        assert_eq!(span["meta"]["env"], "dev");
+        assert_eq!(span["meta"]["http.route"], "/invoices");
+        assert_eq!(span["metrics"][RULE_RATE_KEY], 0.5);
+        assert_eq!(span["metrics"][SAMPLING_PRIORITY_KEY], 1.0);
+    }
+
+    #[test]
+    fn test_pretty_output() {
+        let output = export(ConsoleFormat::Pretty, span());
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            r#"billing component "resource" type="web" duration=1s"#
+        );
+        assert_eq!(lines[1], "    trace_id=7 span_id=5 parent_id=3");
+        assert!(lines.contains(&r#"    http.route="/invoices""#));
+        assert!(lines.contains(&"    _dd.rule_psr=0.5"));
+    }
+}
@@ -0,0 +1,151 @@
+//! Minimal [DDSketch](https://www.vldb.org/pvldb/vol12/p2195-masson.pdf) for the latency
+//! distributions of the trace stats.
+//!
+//! Only what the agent needs is implemented: adding values to a logarithmic mapping with a
+//! dense store, and encoding the sketch in the protobuf format of
+//! [sketches-go](https://github.com/DataDog/sketches-go/blob/v1.4.2/ddsketch/pb/ddsketch.proto).
+
+use std::collections::BTreeMap;
+
+/// Relative accuracy of the quantiles, the one used by Datadog's tracers.
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// A sketch of positive values. Values too small to be indexed count as zeros.
+#[derive(Debug, Clone)]
+pub(super) struct DDSketch {
+    gamma: f64,
+    multiplier: f64,
+    min_indexable_value: f64,
+    bins: BTreeMap<i32, f64>,
+    zero_count: f64,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        let multiplier = 1.0 / gamma.ln();
+        Self {
+            gamma,
+            multiplier,
+            min_indexable_value: f64::max(
+                ((i32::MIN as f64) / multiplier + 1.0).exp(),
+                f64::MIN_POSITIVE * gamma,
+            ),
+            bins: BTreeMap::new(),
+            zero_count: 0.0,
+        }
+    }
+}
+
+impl DDSketch {
+    pub(super) fn add(&mut self, value: f64) {
+        if value < self.min_indexable_value {
+            self.zero_count += 1.0;
+            return;
+        }
+        let index = (value.ln() * self.multiplier).ceil() as i32;
+        *self.bins.entry(index).or_default() += 1.0;
+    }
+
+    /// Encodes the sketch as a `DDSketch` protobuf message.
+    pub(super) fn encode(&self) -> Vec<u8> {
+        // IndexMapping { gamma = 1; indexOffset = 2; interpolation = 3 }, the offset and the
+        // interpolation are both zero
+        let mut mapping = Vec::with_capacity(9);
+        write_double(&mut mapping, 1, self.gamma);
+
+        // Store { binCounts = 1; contiguousBinCounts = 2; contiguousBinIndexOffset = 3 }
+        let mut store = Vec::new();
+        if let (Some((&first, _)), Some((&last, _))) =
+            (self.bins.first_key_value(), self.bins.last_key_value())
+        {
+            let mut counts = Vec::with_capacity((last - first + 1) as usize * 8);
+            for index in first..=last {
+                let count = self.bins.get(&index).copied().unwrap_or_default();
+                counts.extend_from_slice(&count.to_le_bytes());
+            }
+            write_bytes(&mut store, 2, &counts);
+            write_tag(&mut store, 3, WIRE_VARINT);
+            write_varint(&mut store, zigzag(first));
+        }
+
+        // DDSketch { mapping = 1; positiveValues = 2; negativeValues = 3; zeroCount = 4 }
+        let mut sketch = Vec::with_capacity(mapping.len() + store.len() + 16);
+        write_bytes(&mut sketch, 1, &mapping);
+        write_bytes(&mut sketch, 2, &store);
+        write_double(&mut sketch, 4, self.zero_count);
+        sketch
+    }
+}
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+
+fn write_tag(buf: &mut Vec<u8>, field: u8, wire_type: u8) {
+    buf.push((field << 3) | wire_type);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_double(buf: &mut Vec<u8>, field: u8, value: f64) {
+    write_tag(buf, field, WIRE_FIXED64);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    write_tag(buf, field, WIRE_LEN);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut sketch = DDSketch::default();
+        sketch.add(0.0);
+        sketch.add(1.0);
+        sketch.add(1.0);
+        sketch.add(1.01);
+
+        let gamma = sketch.gamma.to_le_bytes();
+        let mut expected = vec![0x0a, 9, 0x09];
+        expected.extend_from_slice(&gamma);
+        // two contiguous bins starting at index 0
+        expected.extend_from_slice(&[0x12, 20, 0x12, 16]);
+        expected.extend_from_slice(&2.0f64.to_le_bytes());
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+        expected.extend_from_slice(&[0x18, 0]);
+        expected.push(0x21);
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+
+        assert_eq!(sketch.encode(), expected);
+    }
+
+    #[test]
+    fn test_relative_accuracy() {
+        let sketch = DDSketch::default();
+        for value in [1.0, 42.0, 1_500_000.0, 3.2e10] {
+            let index = (f64::ln(value) * sketch.multiplier).ceil();
+            // the agent maps an index back to the middle of its bin
+            let estimate = 2.0 * sketch.gamma.powf(index) / (1.0 + sketch.gamma);
+
+            assert!((estimate - value).abs() / value <= RELATIVE_ACCURACY);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright The OpenTelemetry Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Span exporter for the Datadog agent. Adapted from [opentelemetry-datadog v0.16.0](https://github.com/open-telemetry/opentelemetry-rust-contrib/tree/opentelemetry-datadog-0.16.0/opentelemetry-datadog/src/exporter)
+//!
+//! The upstream encoder only ever writes `_sampling_priority_v1` and `_dd.measured` as
+//! metrics, which loses the sampling decision made by
+//! [`DatadogSampler`](crate::sampler::DatadogSampler). This exporter writes the priority from
+//! the span's trace state and every numeric `_dd.*` attribute as a metric instead.
+
+mod console;
+mod ddsketch;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod stats;
+mod v05;
+
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use http::{Method, Request, Uri};
+use opentelemetry::trace::TraceError;
+use opentelemetry_datadog::{FieldMappingFn, ModelConfig};
+use opentelemetry_http::{HttpClient, ResponseExt};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+
+use crate::agent::AgentEndpoint;
+use crate::sampler::AgentRates;
+
+pub(crate) use console::ConsoleExporter;
+#[cfg(feature = "otlp")]
+pub(crate) use otlp::OtlpExporter;
+pub(crate) use stats::StatsProcessor;
+
+const TRACES_PATH: &str = "/v0.5/traces";
+const CONTENT_TYPE: &str = "application/msgpack";
+
+/// Header name used to inform the Datadog agent of the number of traces in the payload
+const DATADOG_TRACE_COUNT_HEADER: &str = "X-Datadog-Trace-Count";
+const DATADOG_META_LANG_HEADER: &str = "Datadog-Meta-Lang";
+const DATADOG_META_TRACER_VERSION_HEADER: &str = "Datadog-Meta-Tracer-Version";
+
+/// Mapping of OpenTelemetry spans to Datadog's service, name and resource.
+#[derive(Clone)]
+pub(crate) struct Mapping {
+    pub(crate) service_name: Arc<FieldMappingFn>,
+    pub(crate) name: Arc<FieldMappingFn>,
+    pub(crate) resource: Arc<FieldMappingFn>,
+}
+
+/// Unified service tags written to the meta of every span.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UnifiedTags {
+    pub(crate) env: Option<String>,
+    pub(crate) version: Option<String>,
+}
+
+/// Sends finished spans to the agent's `/v0.5/traces` endpoint.
+pub(crate) struct DatadogExporter {
+    client: Arc<dyn HttpClient>,
+    request_url: Uri,
+    model_config: ModelConfig,
+    mapping: Mapping,
+    unified_tags: UnifiedTags,
+    agent_rates: AgentRates,
+    client_computed_stats: bool,
+    resource: Option<Resource>,
+}
+
+impl DatadogExporter {
+    pub(crate) fn new(
+        agent: &AgentEndpoint,
+        service_name: &str,
+        mapping: Mapping,
+        unified_tags: UnifiedTags,
+        agent_rates: AgentRates,
+    ) -> Result<Self, TraceError> {
+        let request_url = format!("{}{TRACES_PATH}", agent.base_url())
+            .parse::<Uri>()
+            .map_err(|err| TraceError::from(format!("invalid agent url: {err}")))?;
+
+        let mut model_config = ModelConfig::default();
+        model_config.service_name = service_name.to_string();
+
+        Ok(Self {
+            client: Arc::new(agent.client()?),
+            request_url,
+            model_config,
+            mapping,
+            unified_tags,
+            agent_rates,
+            client_computed_stats: false,
+            resource: None,
+        })
+    }
+
+    /// Tells the agent that the stats of the traces are computed by a [`StatsProcessor`].
+    pub(crate) fn with_client_computed_stats(mut self, enabled: bool) -> Self {
+        self.client_computed_stats = enabled;
+        self
+    }
+
+    fn build_request(&self, mut batch: Vec<SpanData>) -> Result<Request<Vec<u8>>, OTelSdkError> {
+        let traces = group_into_traces(&mut batch);
+        let trace_count = traces.len();
+        let data = v05::encode(
+            &self.model_config,
+            &traces,
+            &self.mapping,
+            &self.unified_tags,
+            self.resource.as_ref(),
+        )
+        .map_err(|err| OTelSdkError::InternalFailure(format!("{err:?}")))?;
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.request_url.clone())
+            .header(http::header::CONTENT_TYPE, CONTENT_TYPE)
+            .header(DATADOG_TRACE_COUNT_HEADER, trace_count)
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            );
+        if self.client_computed_stats {
+            request = request.header(stats::DATADOG_CLIENT_COMPUTED_STATS_HEADER, "yes");
+        }
+        request
+            .body(data)
+            .map_err(|err| OTelSdkError::InternalFailure(format!("{err:?}")))
+    }
+}
+
+impl Debug for DatadogExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatadogExporter")
+            .field("model_config", &self.model_config)
+            .field("request_url", &self.request_url)
+            .field("unified_tags", &self.unified_tags)
+            .finish()
+    }
+}
+
+fn group_into_traces(spans: &mut [SpanData]) -> Vec<&[SpanData]> {
+    if spans.is_empty() {
+        return vec![];
+    }
+
+    spans.sort_unstable_by_key(|x| x.span_context.trace_id().to_bytes());
+
+    let mut traces: Vec<&[SpanData]> = Vec::with_capacity(spans.len());
+
+    let mut start = 0;
+    let mut start_trace_id = spans[start].span_context.trace_id();
+    for (idx, span) in spans.iter().enumerate() {
+        let current_trace_id = span.span_context.trace_id();
+        if start_trace_id != current_trace_id {
+            traces.push(&spans[start..idx]);
+            start = idx;
+            start_trace_id = current_trace_id;
+        }
+    }
+    traces.push(&spans[start..]);
+    traces
+}
+
+async fn send_request(
+    client: Arc<dyn HttpClient>,
+    request: Request<Vec<u8>>,
+    agent_rates: AgentRates,
+) -> OTelSdkResult {
+    let response = client
+        .send_bytes(request.map(Into::into))
+        .await
+        .map_err(|err| OTelSdkError::InternalFailure(format!("HTTP request failed: {err}")))?;
+
+    let response = response
+        .error_for_status()
+        .map_err(|err| OTelSdkError::InternalFailure(format!("HTTP response error: {err}")))?;
+    agent_rates.update(response.body());
+
+    Ok(())
+}
+
+impl SpanExporter for DatadogExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, OTelSdkResult> {
+        let request = match self.build_request(batch) {
+            Ok(request) => request,
+            Err(err) => return Box::pin(std::future::ready(Err(err))),
+        };
+
+        Box::pin(send_request(
+            self.client.clone(),
+            request,
+            self.agent_rates.clone(),
+        ))
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = Some(resource.clone());
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceState;
+    use opentelemetry::trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId};
+    use opentelemetry::{InstrumentationScope, KeyValue, Value};
+    use opentelemetry_http::Bytes;
+    use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime};
+
+    /// Collects the exported spans, to inspect them in tests.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct CapturingExporter(pub(crate) Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for CapturingExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, OTelSdkResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    pub(crate) fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+        span.attributes
+            .iter()
+            .find(|it| it.key.as_str() == key)
+            .map(|it| &it.value)
+    }
+
+    pub(crate) fn get_span(trace_id: u128, parent_span_id: u64, span_id: u64) -> SpanData {
+        get_span_with(trace_id, parent_span_id, span_id, vec![], TraceState::NONE)
+    }
+
+    pub(crate) fn get_span_with(
+        trace_id: u128,
+        parent_span_id: u64,
+        span_id: u64,
+        attributes: Vec<KeyValue>,
+        trace_state: TraceState,
+    ) -> SpanData {
+        let span_context = SpanContext::new(
+            TraceId::from(trace_id),
+            SpanId::from(span_id),
+            TraceFlags::SAMPLED,
+            false,
+            trace_state,
+        );
+
+        let start_time = SystemTime::UNIX_EPOCH;
+        let end_time = start_time.checked_add(Duration::from_secs(1)).unwrap();
+
+        SpanData {
+            span_context,
+            parent_span_id: SpanId::from(parent_span_id),
+            span_kind: SpanKind::Client,
+            name: "resource".into(),
+            start_time,
+            end_time,
+            attributes,
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status: Status::Ok,
+            instrumentation_scope: InstrumentationScope::builder("component").build(),
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_group() {
+        let mut batch = vec![get_span(1, 1, 1), get_span(2, 2, 2), get_span(1, 1, 3)];
+        let expected = vec![
+            vec![get_span(1, 1, 1), get_span(1, 1, 3)],
+            vec![get_span(2, 2, 2)],
+        ];
+
+        let mut traces = group_into_traces(&mut batch);
+        // the agent does not care about the order, it only needs to compare here
+        traces.sort_by_key(|t| u128::from_be_bytes(t[0].span_context.trace_id().to_bytes()));
+
+        assert_eq!(traces, expected);
+    }
+
+    #[test]
+    fn test_request_url() {
+        let exporter = DatadogExporter::new(
+            &"http://localhost:8126/".parse().unwrap(),
+            "service",
+            v05::tests::default_mapping(),
+            UnifiedTags::default(),
+            AgentRates::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            exporter.request_url.to_string(),
+            "http://localhost:8126/v0.5/traces"
+        );
+    }
+
+    #[test]
+    fn test_client_computed_stats_header() {
+        let exporter = DatadogExporter::new(
+            &"http://localhost:8126".parse().unwrap(),
+            "service",
+            v05::tests::default_mapping(),
+            UnifiedTags::default(),
+            AgentRates::default(),
+        )
+        .unwrap();
+        let request = exporter.build_request(vec![get_span(1, 0, 1)]).unwrap();
+        assert!(
+            !request
+                .headers()
+                .contains_key(stats::DATADOG_CLIENT_COMPUTED_STATS_HEADER)
+        );
+
+        let exporter = exporter.with_client_computed_stats(true);
+        let request = exporter.build_request(vec![get_span(1, 0, 1)]).unwrap();
+        assert_eq!(
+            request.headers()[stats::DATADOG_CLIENT_COMPUTED_STATS_HEADER],
+            "yes"
+        );
+    }
+
+    #[derive(Debug)]
+    struct AgentStub;
+
+    #[async_trait::async_trait]
+    impl HttpClient for AgentStub {
+        async fn send_bytes(
+            &self,
+            _request: Request<Bytes>,
+        ) -> Result<http::Response<Bytes>, opentelemetry_http::HttpError> {
+            Ok(http::Response::new(Bytes::from_static(
+                br#"{"rate_by_service": {"service:billing,env:": 0.25}}"#,
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_rates_updated_from_response() {
+        let agent_rates = AgentRates::default();
+        let request = Request::post("http://localhost:8126/v0.5/traces")
+            .body(Vec::new())
+            .unwrap();
+
+        send_request(Arc::new(AgentStub), request, agent_rates.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(agent_rates.rate("service:billing,env:"), 0.25);
+    }
+}
@@ -0,0 +1,166 @@
+//! OTLP span exporter, for environments running an OpenTelemetry Collector instead of the
+//! Datadog agent.
+//!
+//! Datadog's OTLP intake derives the operation and resource names on its own, which rarely
+//! match what [`DatadogExporter`](super::DatadogExporter) sends. To keep the names stable
+//! across backends, every span carries the result of the [`Mapping`] in the `service.name`,
+//! `operation.name` and `resource.name` attributes, which take precedence in the intake.
+
+use std::fmt::{self, Debug};
+
+use futures_util::future::BoxFuture;
+use opentelemetry::KeyValue;
+use opentelemetry::trace::TraceError;
+use opentelemetry_datadog::ModelConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+
+use super::Mapping;
+use crate::config::OtlpProtocol;
+
+const OPERATION_NAME_KEY: &str = "operation.name";
+const RESOURCE_NAME_KEY: &str = "resource.name";
+const SERVICE_NAME_KEY: &str = "service.name";
+const TRACES_PATH: &str = "/v1/traces";
+
+/// Sends finished spans to an OTLP endpoint, named as the Datadog exporter would.
+pub(crate) struct OtlpExporter {
+    inner: opentelemetry_otlp::SpanExporter,
+    model_config: ModelConfig,
+    mapping: Mapping,
+}
+
+impl OtlpExporter {
+    /// Builds the exporter. Without an endpoint, the `OTEL_EXPORTER_OTLP_*` defaults of
+    /// `opentelemetry-otlp` apply.
+    pub(crate) fn new(
+        endpoint: Option<&str>,
+        protocol: OtlpProtocol,
+        service_name: &str,
+        mapping: Mapping,
+    ) -> Result<Self, TraceError> {
+        use opentelemetry_otlp::WithExportConfig;
+
+        let inner = match protocol {
+            OtlpProtocol::Grpc => {
+                let mut builder = opentelemetry_otlp::SpanExporter::builder().with_tonic();
+                if let Some(endpoint) = endpoint {
+                    builder = builder.with_endpoint(endpoint);
+                }
+                builder.build()?
+            }
+            OtlpProtocol::HttpProtobuf => {
+                let mut builder = opentelemetry_otlp::SpanExporter::builder().with_http();
+                // unlike the environment variable, an explicit endpoint is used verbatim
+                if let Some(endpoint) = endpoint {
+                    builder = builder
+                        .with_endpoint(format!("{}{TRACES_PATH}", endpoint.trim_end_matches('/')));
+                }
+                builder.build()?
+            }
+        };
+
+        let mut model_config = ModelConfig::default();
+        model_config.service_name = service_name.to_string();
+
+        Ok(Self {
+            inner,
+            model_config,
+            mapping,
+        })
+    }
+}
+
+impl Debug for OtlpExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OtlpExporter")
+            .field("inner", &self.inner)
+            .field("model_config", &self.model_config)
+            .finish()
+    }
+}
+
+/// The Datadog names of a span, as attributes understood by the OTLP intake.
+fn mapped_attributes(
+    span: &SpanData,
+    model_config: &ModelConfig,
+    mapping: &Mapping,
+) -> [KeyValue; 3] {
+    [
+        KeyValue::new(
+            SERVICE_NAME_KEY,
+            (mapping.service_name)(span, model_config).to_string(),
+        ),
+        KeyValue::new(
+            OPERATION_NAME_KEY,
+            (mapping.name)(span, model_config).to_string(),
+        ),
+        KeyValue::new(
+            RESOURCE_NAME_KEY,
+            (mapping.resource)(span, model_config).to_string(),
+        ),
+    ]
+}
+
+impl SpanExporter for OtlpExporter {
+    fn export(&mut self, mut batch: Vec<SpanData>) -> BoxFuture<'static, OTelSdkResult> {
+        for span in &mut batch {
+            let attributes = mapped_attributes(span, &self.model_config, &self.mapping);
+            span.attributes.extend(attributes);
+        }
+        self.inner.export(batch)
+    }
+
+    fn shutdown(&mut self) -> OTelSdkResult {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::get_span_with;
+    use crate::exporter::v05::tests::default_mapping;
+    use opentelemetry::trace::TraceState;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mapped_attributes() {
+        let span = get_span_with(7, 1, 99, vec![], TraceState::NONE);
+        let mut model_config = ModelConfig::default();
+        model_config.service_name = "billing".to_string();
+
+        let mut mapping = default_mapping();
+        mapping.resource = Arc::new(|_, _| "GET /invoices");
+
+        assert_eq!(
+            mapped_attributes(&span, &model_config, &mapping),
+            [
+                KeyValue::new("service.name", "billing"),
+                KeyValue::new("operation.name", "component"),
+                KeyValue::new("resource.name", "GET /invoices"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_http_exporter() {
+        let exporter = OtlpExporter::new(
+            Some("http://collector:4318/"),
+            OtlpProtocol::HttpProtobuf,
+            "billing",
+            default_mapping(),
+        );
+
+        assert!(exporter.is_ok());
+    }
+}
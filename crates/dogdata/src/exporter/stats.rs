@@ -0,0 +1,583 @@
+//! Client-side trace stats, sent to the agent's `/v0.6/stats` endpoint.
+//!
+//! The agent derives the hits, errors and latency metrics of a service from the traces it
+//! receives, which means sending it every trace, rejected ones included. Instead, the
+//! [`StatsProcessor`] sees every finished span, sampled or not, aggregates the top-level and
+//! measured ones into 10 second buckets, and the trace requests carry
+//! `Datadog-Client-Computed-Stats` so that the agent does not count them again. The
+//! rejected traces are then dropped before export.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, SystemTime};
+
+use http::{Method, Request, Uri};
+use opentelemetry::Value;
+use opentelemetry::trace::{SpanId, SpanKind, Status, TraceError};
+use opentelemetry_datadog::{DatadogTraceState, ModelConfig};
+use opentelemetry_http::{HttpClient, ResponseExt};
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use rmp::encode::ValueWriteError;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use super::ddsketch::DDSketch;
+use super::{
+    CONTENT_TYPE, DATADOG_META_LANG_HEADER, DATADOG_META_TRACER_VERSION_HEADER, Mapping,
+    UnifiedTags,
+};
+use crate::agent::AgentEndpoint;
+
+const STATS_PATH: &str = "/v0.6/stats";
+const BUCKET_DURATION: Duration = Duration::from_secs(10);
+/// How long [`SpanProcessor::force_flush`] and [`SpanProcessor::shutdown`] wait for the
+/// pending stats to be sent.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Header telling the agent that the stats of the traces are computed by the tracer.
+pub(super) const DATADOG_CLIENT_COMPUTED_STATS_HEADER: &str = "Datadog-Client-Computed-Stats";
+
+const HTTP_STATUS_CODE_KEYS: [&str; 2] = ["http.response.status_code", "http.status_code"];
+
+/// The dimensions the stats are grouped by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AggregationKey {
+    service: String,
+    name: String,
+    resource: String,
+    span_type: String,
+    http_status_code: u32,
+}
+
+#[derive(Debug, Default)]
+struct GroupedStats {
+    hits: u64,
+    errors: u64,
+    duration: u64,
+    top_level_hits: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+type Bucket = HashMap<AggregationKey, GroupedStats>;
+
+/// Entry spans, which is what the agent considers top-level in the absence of the parent's
+/// service: roots and the spans receiving a request or a message.
+fn is_top_level(span: &SpanData) -> bool {
+    span.parent_span_id == SpanId::INVALID
+        || matches!(span.span_kind, SpanKind::Server | SpanKind::Consumer)
+}
+
+/// Spans marked with `_dd.measured`, and the spans calling other services.
+fn is_measured(span: &SpanData) -> bool {
+    span.span_context.trace_state().measuring_enabled()
+        || matches!(span.span_kind, SpanKind::Client | SpanKind::Producer)
+}
+
+fn http_status_code(span: &SpanData) -> u32 {
+    span.attributes
+        .iter()
+        .find(|kv| HTTP_STATUS_CODE_KEYS.contains(&kv.key.as_str()))
+        .and_then(|kv| match &kv.value {
+            Value::I64(code) => u32::try_from(*code).ok(),
+            value => value.as_str().parse().ok(),
+        })
+        .unwrap_or_default()
+}
+
+fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// Aggregates spans into buckets aligned on [`BUCKET_DURATION`], by end time.
+struct Concentrator {
+    model_config: ModelConfig,
+    mapping: Mapping,
+    buckets: BTreeMap<u64, Bucket>,
+}
+
+impl Concentrator {
+    fn new(service_name: &str, mapping: Mapping) -> Self {
+        let mut model_config = ModelConfig::default();
+        model_config.service_name = service_name.to_string();
+
+        Self {
+            model_config,
+            mapping,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, span: &SpanData) {
+        let top_level = is_top_level(span);
+        if !top_level && !is_measured(span) {
+            return;
+        }
+
+        let end = nanos_since_epoch(span.end_time);
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let bucket_duration = BUCKET_DURATION.as_nanos() as u64;
+        let key = AggregationKey {
+            service: (self.mapping.service_name)(span, &self.model_config).to_string(),
+            name: (self.mapping.name)(span, &self.model_config).to_string(),
+            resource: (self.mapping.resource)(span, &self.model_config).to_string(),
+            span_type: span
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == "span.type")
+                .map(|kv| kv.value.to_string())
+                .unwrap_or_default(),
+            http_status_code: http_status_code(span),
+        };
+
+        let stats = self
+            .buckets
+            .entry(end - end % bucket_duration)
+            .or_default()
+            .entry(key)
+            .or_default();
+        stats.hits += 1;
+        stats.duration += duration;
+        if top_level {
+            stats.top_level_hits += 1;
+        }
+        if let Status::Error { .. } = span.status {
+            stats.errors += 1;
+            stats.error_summary.add(duration as f64);
+        } else {
+            stats.ok_summary.add(duration as f64);
+        }
+    }
+
+    /// Removes the buckets that are complete at `now`, or all of them when `force` is set.
+    /// The previous bucket is kept for one more interval, for the spans finishing late.
+    fn flush(&mut self, now: SystemTime, force: bool) -> BTreeMap<u64, Bucket> {
+        if force {
+            return std::mem::take(&mut self.buckets);
+        }
+        let bucket_duration = BUCKET_DURATION.as_nanos() as u64;
+        let now = nanos_since_epoch(now);
+        let oldest_kept = (now - now % bucket_duration).saturating_sub(bucket_duration);
+        let kept = self.buckets.split_off(&oldest_kept);
+        std::mem::replace(&mut self.buckets, kept)
+    }
+}
+
+/// Encodes and sends the flushed buckets.
+struct StatsSender {
+    client: Arc<dyn HttpClient>,
+    request_url: Uri,
+    service: String,
+    unified_tags: UnifiedTags,
+    sequence: u64,
+}
+
+impl StatsSender {
+    // Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/7.50.0/pkg/proto/datadog/trace/stats.proto
+    //
+    // The payload is a `ClientStatsPayload` map, whose `Stats` are `ClientStatsBucket` maps
+    // of `ClientGroupedStats`. The `OkSummary` and `ErrorSummary` are DDSketch protobufs.
+    fn encode(&self, buckets: &BTreeMap<u64, Bucket>) -> Result<Vec<u8>, ValueWriteError> {
+        let mut payload = Vec::new();
+        rmp::encode::write_map_len(&mut payload, 8)?;
+        write_str_field(&mut payload, "Hostname", "")?;
+        write_str_field(
+            &mut payload,
+            "Env",
+            self.unified_tags.env.as_deref().unwrap_or_default(),
+        )?;
+        write_str_field(
+            &mut payload,
+            "Version",
+            self.unified_tags.version.as_deref().unwrap_or_default(),
+        )?;
+        write_str_field(&mut payload, "Service", &self.service)?;
+        write_str_field(&mut payload, "Lang", "rust")?;
+        write_str_field(&mut payload, "TracerVersion", env!("CARGO_PKG_VERSION"))?;
+        rmp::encode::write_str(&mut payload, "Sequence")?;
+        rmp::encode::write_u64(&mut payload, self.sequence)?;
+
+        rmp::encode::write_str(&mut payload, "Stats")?;
+        rmp::encode::write_array_len(&mut payload, buckets.len() as u32)?;
+        for (start, bucket) in buckets {
+            rmp::encode::write_map_len(&mut payload, 3)?;
+            rmp::encode::write_str(&mut payload, "Start")?;
+            rmp::encode::write_u64(&mut payload, *start)?;
+            rmp::encode::write_str(&mut payload, "Duration")?;
+            rmp::encode::write_u64(&mut payload, BUCKET_DURATION.as_nanos() as u64)?;
+
+            rmp::encode::write_str(&mut payload, "Stats")?;
+            rmp::encode::write_array_len(&mut payload, bucket.len() as u32)?;
+            for (key, stats) in bucket {
+                rmp::encode::write_map_len(&mut payload, 12)?;
+                write_str_field(&mut payload, "Service", &key.service)?;
+                write_str_field(&mut payload, "Name", &key.name)?;
+                write_str_field(&mut payload, "Resource", &key.resource)?;
+                write_str_field(&mut payload, "Type", &key.span_type)?;
+                rmp::encode::write_str(&mut payload, "HTTPStatusCode")?;
+                rmp::encode::write_u32(&mut payload, key.http_status_code)?;
+                rmp::encode::write_str(&mut payload, "Synthetics")?;
+                rmp::encode::write_bool(&mut payload, false)
+                    .map_err(ValueWriteError::InvalidDataWrite)?;
+                for (name, value) in [
+                    ("Hits", stats.hits),
+                    ("Errors", stats.errors),
+                    ("Duration", stats.duration),
+                    ("TopLevelHits", stats.top_level_hits),
+                ] {
+                    rmp::encode::write_str(&mut payload, name)?;
+                    rmp::encode::write_u64(&mut payload, value)?;
+                }
+                rmp::encode::write_str(&mut payload, "OkSummary")?;
+                rmp::encode::write_bin(&mut payload, &stats.ok_summary.encode())?;
+                rmp::encode::write_str(&mut payload, "ErrorSummary")?;
+                rmp::encode::write_bin(&mut payload, &stats.error_summary.encode())?;
+            }
+        }
+
+        Ok(payload)
+    }
+
+    async fn send(&mut self, buckets: BTreeMap<u64, Bucket>) -> OTelSdkResult {
+        if buckets.is_empty() {
+            return Ok(());
+        }
+        let data = self
+            .encode(&buckets)
+            .map_err(|err| OTelSdkError::InternalFailure(format!("{err:?}")))?;
+        self.sequence += 1;
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.request_url.clone())
+            .header(http::header::CONTENT_TYPE, CONTENT_TYPE)
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            )
+            .body(data.into())
+            .map_err(|err| OTelSdkError::InternalFailure(format!("{err:?}")))?;
+        self.client
+            .send_bytes(request)
+            .await
+            .map_err(|err| OTelSdkError::InternalFailure(format!("HTTP request failed: {err}")))?
+            .error_for_status()
+            .map_err(|err| OTelSdkError::InternalFailure(format!("HTTP response error: {err}")))?;
+
+        Ok(())
+    }
+}
+
+fn write_str_field(payload: &mut Vec<u8>, key: &str, value: &str) -> Result<(), ValueWriteError> {
+    rmp::encode::write_str(payload, key)?;
+    rmp::encode::write_str(payload, value)
+}
+
+enum Message {
+    Flush(mpsc::Sender<()>),
+    Shutdown(mpsc::Sender<()>),
+}
+
+/// Flushes the concentrator every [`BUCKET_DURATION`], and everything on a flush or
+/// shutdown.
+async fn run(
+    concentrator: Arc<Mutex<Concentrator>>,
+    mut sender: StatsSender,
+    mut messages: UnboundedReceiver<Message>,
+) {
+    let flush = |force| {
+        concentrator
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .flush(SystemTime::now(), force)
+    };
+    loop {
+        let (buckets, message) = match tokio::time::timeout(BUCKET_DURATION, messages.recv()).await
+        {
+            Err(_) => (flush(false), None),
+            Ok(message) => (flush(true), Some(message)),
+        };
+        if let Err(err) = sender.send(buckets).await {
+            tracing::warn!(target: "otel::stats", "failed to send the trace stats: {err}");
+        }
+        match message {
+            None => {}
+            Some(Some(Message::Flush(done))) => {
+                let _ = done.send(());
+            }
+            Some(Some(Message::Shutdown(done))) => {
+                let _ = done.send(());
+                return;
+            }
+            // the processor was dropped without being shut down
+            Some(None) => return,
+        }
+    }
+}
+
+/// Computes the stats of every finished span and sends them to the agent in the
+/// background. Requires a tokio runtime.
+pub(crate) struct StatsProcessor {
+    concentrator: Arc<Mutex<Concentrator>>,
+    messages: UnboundedSender<Message>,
+}
+
+impl StatsProcessor {
+    pub(crate) fn new(
+        agent: &AgentEndpoint,
+        service_name: &str,
+        mapping: Mapping,
+        unified_tags: UnifiedTags,
+    ) -> Result<Self, TraceError> {
+        let request_url = format!("{}{STATS_PATH}", agent.base_url())
+            .parse::<Uri>()
+            .map_err(|err| TraceError::from(format!("invalid agent url: {err}")))?;
+
+        let concentrator = Arc::new(Mutex::new(Concentrator::new(service_name, mapping)));
+        let sender = StatsSender {
+            client: Arc::new(agent.client()?),
+            request_url,
+            service: service_name.to_string(),
+            unified_tags,
+            sequence: 0,
+        };
+        let (messages, receiver) = unbounded_channel();
+        tokio::spawn(run(concentrator.clone(), sender, receiver));
+
+        Ok(Self {
+            concentrator,
+            messages,
+        })
+    }
+
+    /// Sends a message to the background task, and waits for the stats to be sent.
+    fn send(&self, message: fn(mpsc::Sender<()>) -> Message) -> OTelSdkResult {
+        let (done, flushed) = mpsc::channel();
+        self.messages
+            .send(message(done))
+            .map_err(|_| OTelSdkError::AlreadyShutdown)?;
+        flushed
+            .recv_timeout(FLUSH_TIMEOUT)
+            .map_err(|_| OTelSdkError::Timeout(FLUSH_TIMEOUT))
+    }
+}
+
+impl Debug for StatsProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatsProcessor").finish_non_exhaustive()
+    }
+}
+
+impl SpanProcessor for StatsProcessor {
+    fn on_start(&self, _span: &mut Span, _cx: &opentelemetry::Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.concentrator
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .add(&span);
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.send(Message::Flush)
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.send(Message::Shutdown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::get_span_with;
+    use crate::exporter::v05::tests::default_mapping;
+    use opentelemetry::KeyValue;
+    use opentelemetry::trace::TraceState;
+    use opentelemetry_http::Bytes;
+    use serde::Deserialize;
+    use serde::de::IgnoredAny;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Payload {
+        env: String,
+        service: String,
+        sequence: u64,
+        stats: Vec<StatsBucket>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct StatsBucket {
+        start: u64,
+        duration: u64,
+        stats: Vec<GroupedStats>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct GroupedStats {
+        name: String,
+        resource: String,
+        #[serde(rename = "HTTPStatusCode")]
+        http_status_code: u32,
+        hits: u64,
+        errors: u64,
+        duration: u64,
+        top_level_hits: u64,
+        #[allow(dead_code)]
+        ok_summary: IgnoredAny,
+    }
+
+    fn span(parent_span_id: u64, kind: SpanKind, attributes: Vec<KeyValue>) -> SpanData {
+        let mut span = get_span_with(7, parent_span_id, 1, attributes, TraceState::NONE);
+        span.span_kind = kind;
+        span
+    }
+
+    #[test]
+    fn test_aggregates_top_level_and_measured_spans() {
+        let mut concentrator = Concentrator::new("billing", default_mapping());
+        let status = || vec![KeyValue::new("http.response.status_code", 200)];
+        concentrator.add(&span(0, SpanKind::Server, status()));
+        concentrator.add(&span(0, SpanKind::Server, status()));
+        let mut failed = span(0, SpanKind::Server, status());
+        failed.status = Status::error("boom");
+        concentrator.add(&failed);
+        // measured but not top-level
+        concentrator.add(&span(3, SpanKind::Client, vec![]));
+        // neither
+        concentrator.add(&span(3, SpanKind::Internal, vec![]));
+
+        let buckets = concentrator.flush(SystemTime::now(), true);
+        assert!(concentrator.buckets.is_empty());
+        let bucket = &buckets[&0];
+        assert_eq!(bucket.len(), 2);
+
+        let server = bucket.iter().find(|(key, _)| key.http_status_code == 200);
+        let (key, stats) = server.unwrap();
+        assert_eq!(key.service, "billing");
+        assert_eq!(key.resource, "resource");
+        assert_eq!((stats.hits, stats.errors, stats.top_level_hits), (3, 1, 3));
+        assert_eq!(stats.duration, 3_000_000_000);
+
+        let client = bucket.iter().find(|(key, _)| key.http_status_code == 0);
+        let (_, stats) = client.unwrap();
+        assert_eq!((stats.hits, stats.errors, stats.top_level_hits), (1, 0, 0));
+    }
+
+    #[test]
+    fn test_flush_keeps_recent_buckets() {
+        let mut concentrator = Concentrator::new("billing", default_mapping());
+        concentrator.add(&span(0, SpanKind::Server, vec![]));
+
+        let start = SystemTime::UNIX_EPOCH;
+        assert!(
+            concentrator
+                .flush(start + Duration::from_secs(15), false)
+                .is_empty()
+        );
+        assert_eq!(
+            concentrator
+                .flush(start + Duration::from_secs(20), false)
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_encode_payload() {
+        let mut concentrator = Concentrator::new("billing", default_mapping());
+        concentrator.add(&span(
+            0,
+            SpanKind::Server,
+            vec![KeyValue::new("http.status_code", "503")],
+        ));
+        let sender = StatsSender {
+            client: Arc::new(AgentEndpoint::Http(String::new()).client().unwrap()),
+            request_url: Uri::from_static("http://localhost:8126/v0.6/stats"),
+            service: "billing".to_string(),
+            unified_tags: UnifiedTags {
+                env: Some("prod".to_string()),
+                version: None,
+            },
+            sequence: 4,
+        };
+
+        let payload = sender
+            .encode(&concentrator.flush(SystemTime::now(), true))
+            .unwrap();
+        let payload: Payload = rmp_serde::from_slice(&payload).unwrap();
+
+        assert_eq!(payload.env, "prod");
+        assert_eq!(payload.service, "billing");
+        assert_eq!(payload.sequence, 4);
+        let bucket = &payload.stats[0];
+        assert_eq!((bucket.start, bucket.duration), (0, 10_000_000_000));
+        let stats = &bucket.stats[0];
+        assert_eq!(stats.name, "component");
+        assert_eq!(stats.resource, "resource");
+        assert_eq!(stats.http_status_code, 503);
+        assert_eq!(
+            (
+                stats.hits,
+                stats.errors,
+                stats.duration,
+                stats.top_level_hits
+            ),
+            (1, 0, 1_000_000_000, 1)
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct AgentStub(Mutex<Vec<Request<Bytes>>>);
+
+    #[async_trait::async_trait]
+    impl HttpClient for AgentStub {
+        async fn send_bytes(
+            &self,
+            request: Request<Bytes>,
+        ) -> Result<http::Response<Bytes>, opentelemetry_http::HttpError> {
+            self.0.lock().unwrap().push(request);
+            Ok(http::Response::new(Bytes::new()))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_force_flush_sends_pending_stats() {
+        let agent = Arc::new(AgentStub::default());
+        let concentrator = Arc::new(Mutex::new(Concentrator::new("billing", default_mapping())));
+        let sender = StatsSender {
+            client: agent.clone(),
+            request_url: Uri::from_static("http://localhost:8126/v0.6/stats"),
+            service: "billing".to_string(),
+            unified_tags: UnifiedTags::default(),
+            sequence: 0,
+        };
+        let (messages, receiver) = unbounded_channel();
+        tokio::spawn(run(concentrator.clone(), sender, receiver));
+        let processor = StatsProcessor {
+            concentrator,
+            messages,
+        };
+
+        processor.on_end(span(0, SpanKind::Server, vec![]));
+        processor.force_flush().unwrap();
+        assert_eq!(agent.0.lock().unwrap().len(), 1);
+
+        // still running after a flush
+        processor.on_end(span(0, SpanKind::Server, vec![]));
+        processor.shutdown().unwrap();
+        assert_eq!(agent.0.lock().unwrap().len(), 2);
+        assert!(processor.force_flush().is_err());
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright The OpenTelemetry Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Version 0.5 of the agent's trace intake. Adapted from [opentelemetry-datadog v0.16.0](https://github.com/open-telemetry/opentelemetry-rust-contrib/blob/opentelemetry-datadog-0.16.0/opentelemetry-datadog/src/exporter/model/v05.rs)
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use opentelemetry::Value;
+use opentelemetry::trace::Status;
+use opentelemetry_datadog::{DatadogTraceState, ModelConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SpanData;
+use rmp::encode::ValueWriteError;
+
+use super::{Mapping, UnifiedTags};
+use crate::propagator::{TRACE_ID_HIGH_TAG, trace_id_high};
+use crate::sampler::{SAMPLING_PRIORITY_KEY, sampling_priority};
+
+// https://github.com/DataDog/datadog-agent/blob/ec96f3c24173ec66ba235bda7710504400d9a000/pkg/trace/traceutil/span.go#L20
+const DD_MEASURED_KEY: &str = "_dd.measured";
+
+const SPAN_NUM_ELEMENTS: u32 = 12;
+const GIT_META_TAGS: Option<(&str, &str)> = match (
+    option_env!("DD_GIT_REPOSITORY_URL"),
+    option_env!("DD_GIT_COMMIT_SHA"),
+) {
+    (Some(repository_url), Some(commit_sha)) => Some((repository_url, commit_sha)),
+    _ => None,
+};
+
+// Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/c076ea9a1ffbde4c76d35343dbc32aecbbf99cb9/pkg/trace/api/version.go
+//
+// The payload is an array containing exactly 2 elements:
+//
+// 	1. An array of all unique strings present in the payload (a dictionary referred to by index).
+// 	2. An array of traces, where each trace is an array of spans. A span is encoded as an array having
+// 	   exactly 12 elements, representing all span properties, in this exact order:
+//
+// 		 0: Service   (uint32)
+// 		 1: Name      (uint32)
+// 		 2: Resource  (uint32)
+// 		 3: TraceID   (uint64)
+// 		 4: SpanID    (uint64)
+// 		 5: ParentID  (uint64)
+// 		 6: Start     (int64)
+// 		 7: Duration  (int64)
+// 		 8: Error     (int32)
+// 		 9: Meta      (map[uint32]uint32)
+// 		10: Metrics   (map[uint32]float64)
+// 		11: Type      (uint32)
+//
+// 	The "uint32" typed values in "Service", "Name", "Resource", "Type", "Meta" and "Metrics" represent
+// 	the index at which the corresponding string is found in the dictionary.
+pub(super) fn encode(
+    model_config: &ModelConfig,
+    traces: &[&[SpanData]],
+    mapping: &Mapping,
+    unified_tags: &UnifiedTags,
+    resource: Option<&Resource>,
+) -> Result<Vec<u8>, ValueWriteError> {
+    let mut interner = StringInterner::default();
+    let mut encoded_traces = Vec::new();
+    encode_traces(
+        &mut encoded_traces,
+        &mut interner,
+        model_config,
+        traces,
+        mapping,
+        unified_tags,
+        resource,
+    )?;
+
+    let mut payload = Vec::with_capacity(traces.len() * 512);
+    rmp::encode::write_array_len(&mut payload, 2)?;
+    interner.write_dictionary(&mut payload)?;
+    payload.append(&mut encoded_traces);
+
+    Ok(payload)
+}
+
+/// Numeric `_dd.*` attributes, such as the sampling rates, are metrics in Datadog's model.
+pub(super) fn as_metric(key: &str, value: &Value) -> Option<f64> {
+    if !key.starts_with("_dd.") {
+        return None;
+    }
+    match value {
+        Value::F64(value) => Some(*value),
+        Value::I64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn encode_traces<'a>(
+    encoded: &mut Vec<u8>,
+    interner: &mut StringInterner<'a>,
+    model_config: &'a ModelConfig,
+    traces: &[&'a [SpanData]],
+    mapping: &'a Mapping,
+    unified_tags: &'a UnifiedTags,
+    resource: Option<&'a Resource>,
+) -> Result<(), ValueWriteError> {
+    rmp::encode::write_array_len(encoded, traces.len() as u32)?;
+
+    for trace in traces {
+        rmp::encode::write_array_len(encoded, trace.len() as u32)?;
+
+        for span in trace.iter() {
+            // Safe until the year 2262 when Datadog will need to change their API
+            let start = span
+                .start_time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as i64;
+
+            let duration = span
+                .end_time
+                .duration_since(span.start_time)
+                .map(|x| x.as_nanos() as i64)
+                .unwrap_or(0);
+
+            let span_type = match span
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == "span.type")
+            {
+                Some(kv) => interner.intern_value(&kv.value),
+                None => interner.intern(""),
+            };
+
+            rmp::encode::write_array_len(encoded, SPAN_NUM_ELEMENTS)?;
+            rmp::encode::write_u32(
+                encoded,
+                interner.intern((mapping.service_name)(span, model_config)),
+            )?;
+            rmp::encode::write_u32(encoded, interner.intern((mapping.name)(span, model_config)))?;
+            rmp::encode::write_u32(
+                encoded,
+                interner.intern((mapping.resource)(span, model_config)),
+            )?;
+            rmp::encode::write_u64(
+                encoded,
+                u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
+            )?;
+            rmp::encode::write_u64(
+                encoded,
+                u64::from_be_bytes(span.span_context.span_id().to_bytes()),
+            )?;
+            rmp::encode::write_u64(encoded, u64::from_be_bytes(span.parent_span_id.to_bytes()))?;
+            rmp::encode::write_i64(encoded, start)?;
+            rmp::encode::write_i64(encoded, duration)?;
+            rmp::encode::write_i32(
+                encoded,
+                match span.status {
+                    Status::Error { .. } => 1,
+                    _ => 0,
+                },
+            )?;
+
+            let mut meta: Vec<(u32, u32)> = Vec::new();
+            let mut metrics: Vec<(u32, f64)> = Vec::new();
+
+            if let Some(resource) = resource {
+                for (key, value) in resource.iter() {
+                    meta.push((interner.intern(key.as_str()), interner.intern_value(value)));
+                }
+            }
+            if let Some(env) = &unified_tags.env {
+                meta.push((interner.intern("env"), interner.intern(env)));
+            }
+            if let Some(version) = &unified_tags.version {
+                meta.push((interner.intern("version"), interner.intern(version)));
+            }
+            for kv in span.attributes.iter() {
+                match as_metric(kv.key.as_str(), &kv.value) {
+                    Some(value) => metrics.push((interner.intern(kv.key.as_str()), value)),
+                    None => meta.push((
+                        interner.intern(kv.key.as_str()),
+                        interner.intern_value(&kv.value),
+                    )),
+                }
+            }
+            if let Some((repository_url, commit_sha)) = GIT_META_TAGS {
+                meta.push((
+                    interner.intern("git.repository_url"),
+                    interner.intern(repository_url),
+                ));
+                meta.push((
+                    interner.intern("git.commit.sha"),
+                    interner.intern(commit_sha),
+                ));
+            }
+
+            // the trace id field only holds the lower 64 bits
+            if let Some(high) = trace_id_high(span.span_context.trace_id()) {
+                meta.push((
+                    interner.intern(TRACE_ID_HIGH_TAG),
+                    interner.intern_cow(Cow::Owned(format!("{high:016x}"))),
+                ));
+            }
+
+            let trace_state = span.span_context.trace_state();
+            let priority = sampling_priority(trace_state)
+                .unwrap_or(if span.span_context.is_sampled() { 1 } else { 0 });
+            metrics.push((interner.intern(SAMPLING_PRIORITY_KEY), priority as f64));
+            metrics.push((
+                interner.intern(DD_MEASURED_KEY),
+                if trace_state.measuring_enabled() {
+                    1.0
+                } else {
+                    0.0
+                },
+            ));
+
+            rmp::encode::write_map_len(encoded, meta.len() as u32)?;
+            for (key, value) in meta {
+                rmp::encode::write_u32(encoded, key)?;
+                rmp::encode::write_u32(encoded, value)?;
+            }
+            rmp::encode::write_map_len(encoded, metrics.len() as u32)?;
+            for (key, value) in metrics {
+                rmp::encode::write_u32(encoded, key)?;
+                rmp::encode::write_f64(encoded, value)?;
+            }
+            rmp::encode::write_u32(encoded, span_type)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The string dictionary of the payload.
+#[derive(Default)]
+struct StringInterner<'a> {
+    strings: Vec<Cow<'a, str>>,
+    indices: HashMap<Cow<'a, str>, u32>,
+}
+
+impl<'a> StringInterner<'a> {
+    fn intern(&mut self, data: &'a str) -> u32 {
+        self.intern_cow(Cow::Borrowed(data))
+    }
+
+    fn intern_value(&mut self, value: &'a Value) -> u32 {
+        self.intern_cow(value.as_str())
+    }
+
+    fn intern_cow(&mut self, data: Cow<'a, str>) -> u32 {
+        if let Some(idx) = self.indices.get(&data) {
+            return *idx;
+        }
+        let idx = self.strings.len() as u32;
+        self.strings.push(data.clone());
+        self.indices.insert(data, idx);
+        idx
+    }
+
+    fn write_dictionary(&self, payload: &mut Vec<u8>) -> Result<(), ValueWriteError> {
+        rmp::encode::write_array_len(payload, self.strings.len() as u32)?;
+        for data in &self.strings {
+            rmp::encode::write_str(payload, data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::exporter::tests::get_span_with;
+    use crate::model::{
+        default_name_mapping, default_resource_mapping, default_service_name_mapping,
+    };
+    use crate::sampler::{RULE_RATE_KEY, with_sampling_priority};
+    use opentelemetry::KeyValue;
+    use opentelemetry::trace::TraceState;
+    use std::sync::Arc;
+
+    type Span = (
+        u32,
+        u32,
+        u32,
+        u64,
+        u64,
+        u64,
+        i64,
+        i64,
+        i32,
+        HashMap<u32, u32>,
+        HashMap<u32, f64>,
+        u32,
+    );
+
+    pub(crate) fn default_mapping() -> Mapping {
+        Mapping {
+            service_name: Arc::new(default_service_name_mapping),
+            name: Arc::new(default_name_mapping),
+            resource: Arc::new(default_resource_mapping),
+        }
+    }
+
+    fn decode(payload: &[u8]) -> (Vec<String>, Vec<Vec<Span>>) {
+        rmp_serde::from_slice(payload).unwrap()
+    }
+
+    #[test]
+    fn test_encode_sampling_decision_as_metrics() {
+        let trace_state = with_sampling_priority(&TraceState::NONE, 2);
+        let span = get_span_with(
+            7,
+            0,
+            1,
+            vec![
+                KeyValue::new(RULE_RATE_KEY, 0.5),
+                KeyValue::new("http.route", "/users/{id}"),
+                KeyValue::new("span.type", "web"),
+            ],
+            trace_state,
+        );
+        let mut model_config = ModelConfig::default();
+        model_config.service_name = "billing".to_string();
+        let unified_tags = UnifiedTags {
+            env: Some("prod".to_string()),
+            version: None,
+        };
+
+        let payload = encode(
+            &model_config,
+            &[&[span]],
+            &default_mapping(),
+            &unified_tags,
+            None,
+        )
+        .unwrap();
+        let (dictionary, traces) = decode(&payload);
+        let span = &traces[0][0];
+        let lookup = |idx: &u32| dictionary[*idx as usize].as_str();
+        let meta: HashMap<_, _> = span.9.iter().map(|(k, v)| (lookup(k), lookup(v))).collect();
+        let metrics: HashMap<_, _> = span.10.iter().map(|(k, v)| (lookup(k), *v)).collect();
+
+        assert_eq!(lookup(&span.0), "billing");
+        assert_eq!(lookup(&span.1), "component");
+        assert_eq!(lookup(&span.2), "resource");
+        assert_eq!(span.3, 7);
+        assert_eq!(lookup(&span.11), "web");
+        assert_eq!(meta.get("env"), Some(&"prod"));
+        assert_eq!(meta.get("http.route"), Some(&"/users/{id}"));
+        assert!(!meta.contains_key(RULE_RATE_KEY));
+        assert_eq!(metrics.get(RULE_RATE_KEY), Some(&0.5));
+        assert_eq!(metrics.get(SAMPLING_PRIORITY_KEY), Some(&2.0));
+        assert_eq!(metrics.get(DD_MEASURED_KEY), Some(&0.0));
+    }
+
+    #[test]
+    fn test_encode_128_bit_trace_id() {
+        let span = get_span_with(
+            0x640c_fd8d_0000_0000_0000_0000_0000_0007,
+            0,
+            1,
+            vec![],
+            TraceState::NONE,
+        );
+
+        let payload = encode(
+            &ModelConfig::default(),
+            &[&[span]],
+            &default_mapping(),
+            &UnifiedTags::default(),
+            None,
+        )
+        .unwrap();
+        let (dictionary, traces) = decode(&payload);
+        let span = &traces[0][0];
+        let meta: HashMap<_, _> = span
+            .9
+            .iter()
+            .map(|(k, v)| {
+                (
+                    dictionary[*k as usize].as_str(),
+                    dictionary[*v as usize].as_str(),
+                )
+            })
+            .collect();
+
+        assert_eq!(span.3, 7);
+        assert_eq!(meta.get(TRACE_ID_HIGH_TAG), Some(&"640cfd8d00000000"));
+    }
+
+    #[test]
+    fn test_encode_defaults_priority_from_sampled_flag() {
+        let span = get_span_with(7, 0, 1, vec![], TraceState::NONE);
+
+        let payload = encode(
+            &ModelConfig::default(),
+            &[&[span]],
+            &default_mapping(),
+            &UnifiedTags::default(),
+            None,
+        )
+        .unwrap();
+        let (dictionary, traces) = decode(&payload);
+        let metrics: HashMap<_, _> = traces[0][0]
+            .10
+            .iter()
+            .map(|(k, v)| (dictionary[*k as usize].as_str(), *v))
+            .collect();
+
+        assert_eq!(metrics.get(SAMPLING_PRIORITY_KEY), Some(&1.0));
+    }
+}
@@ -27,26 +27,93 @@
 //! It also adds the trace ID to the `dd.trace_id` field and the span ID to the
 //! `dd.span_id` field, which is where Datadog looks for these by default
 //! (although the path to the trace ID can be overridden in Datadog).
+//!
+//! With [`with_128_bit_trace_ids`](DatadogFormatter::with_128_bit_trace_ids), trace IDs
+//! whose upper 64 bits are set are written as 32 lowercase hex characters instead, which is
+//! how Datadog correlates logs with 128-bit traces.
+//!
+//! When configured, the unified service tags are emitted as `dd.service`, `dd.env` and
+//! `dd.version` so that logs correlate with traces across deployments.
+//!
+//! Every line also carries Datadog's
+//! [standard attributes](https://docs.datadoghq.com/standard-attributes/?product=log), so
+//! that logs are parsed without a custom pipeline: `status`, `logger.name` (the target),
+//! `logger.thread_name`, `logger.method_name` (the current span), `logger.file_name` and
+//! `logger.line_number`. An `error` field is written as `error.message`, along with
+//! `error.stack` when recorded as a [`std::error::Error`], and an `error.type` field as
+//! `error.kind`: the type behind a `dyn Error` is unknown, as for
+//! [`record_error`](crate::error::record_error). Each of them can be renamed or omitted,
+//! see [`LogKey`], e.g. to keep the `level` and `target` keys of the `tracing` JSON format.
+//!
+//! Fields recorded on the enclosing spans can be added to the line as well, either
+//! flattened with [`with_span_fields`](DatadogFormatter::with_span_fields) or as a `spans`
+//! list with [`with_span_list`](DatadogFormatter::with_span_list). Both read the span fields
+//! as formatted by [`JsonFields`](tracing_subscriber::fmt::format::JsonFields), the field
+//! formatter of a `tracing_subscriber::fmt::layer().json()`.
+//!
+//! The human readable formats of `tracing_subscriber` do not know about traces, so
+//! [`CorrelatedFormat`] wraps them to append `dd.trace_id` and `dd.span_id` to the lines
+//! logged within a span, which makes local logs searchable by trace as well.
+//!
+//! With a [`Redactor`], sensitive values are redacted from the fields of the event, the
+//! `error.*` attributes and the span fields before anything is serialized.
 
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::fmt;
 use std::io;
+use std::str::FromStr;
 
 use chrono::Utc;
 use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
 use serde::Serialize;
 use serde::ser::{SerializeMap, Serializer as _};
-use tracing::{Event, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
 use tracing_opentelemetry::OtelData;
 
-use tracing_serde::AsSerde;
+use tracing_serde::SerdeMapVisitor;
 use tracing_subscriber::fmt::format::Writer;
-use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
 use tracing_subscriber::registry::{LookupSpan, SpanRef};
 
+use crate::error::ERROR_TYPE;
+use crate::redaction::{REDACTED, Redactor};
+
 #[derive(Serialize)]
 struct DatadogId(u64);
 
+/// A trace ID as written in the logs: the lower 64 bits in decimal, or the full 128 bits in
+/// hex.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum DatadogTraceId {
+    Id64(DatadogId),
+    Id128(String),
+}
+
+impl DatadogTraceId {
+    fn new(value: TraceId, use_128_bit: bool) -> Self {
+        let id = u128::from_be_bytes(value.to_bytes());
+        if use_128_bit && id >> 64 != 0 {
+            Self::Id128(format!("{id:032x}"))
+        } else {
+            Self::Id64(value.into())
+        }
+    }
+}
+
+impl fmt::Display for DatadogTraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id64(id) => write!(f, "{}", id.0),
+            Self::Id128(id) => f.write_str(id),
+        }
+    }
+}
+
 struct TraceInfo {
-    trace_id: DatadogId,
+    trace_id: TraceId,
     span_id: DatadogId,
 }
 
@@ -74,14 +141,309 @@ where
             o.builder.trace_id.unwrap_or(TraceId::INVALID)
         };
         TraceInfo {
-            trace_id: trace_id.into(),
+            trace_id,
             span_id: o.builder.span_id.unwrap_or(SpanId::INVALID).into(),
         }
     })
 }
 
+/// A standard attribute written by the [`DatadogFormatter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogKey {
+    Timestamp,
+    Status,
+    LoggerName,
+    ThreadName,
+    MethodName,
+    FileName,
+    LineNumber,
+    ErrorKind,
+    ErrorMessage,
+    ErrorStack,
+}
+
+impl LogKey {
+    const ALL: [LogKey; 10] = [
+        LogKey::Timestamp,
+        LogKey::Status,
+        LogKey::LoggerName,
+        LogKey::ThreadName,
+        LogKey::MethodName,
+        LogKey::FileName,
+        LogKey::LineNumber,
+        LogKey::ErrorKind,
+        LogKey::ErrorMessage,
+        LogKey::ErrorStack,
+    ];
+
+    /// The key the attribute is written under unless renamed.
+    pub const fn default_name(self) -> &'static str {
+        match self {
+            LogKey::Timestamp => "timestamp",
+            LogKey::Status => "status",
+            LogKey::LoggerName => "logger.name",
+            LogKey::ThreadName => "logger.thread_name",
+            LogKey::MethodName => "logger.method_name",
+            LogKey::FileName => "logger.file_name",
+            LogKey::LineNumber => "logger.line_number",
+            LogKey::ErrorKind => "error.kind",
+            LogKey::ErrorMessage => "error.message",
+            LogKey::ErrorStack => "error.stack",
+        }
+    }
+}
+
+impl FromStr for LogKey {
+    type Err = String;
+
+    /// Parses the [`default_name`](Self::default_name) of the key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LogKey::ALL
+            .into_iter()
+            .find(|it| it.default_name() == s.trim())
+            .ok_or_else(|| format!("unknown log key `{s}`"))
+    }
+}
+
 // mostly stolen from here: https://github.com/tokio-rs/tracing/issues/1531
-pub struct DatadogFormatter;
+#[derive(Debug, Clone, Default)]
+pub struct DatadogFormatter {
+    service: Option<String>,
+    env: Option<String>,
+    version: Option<String>,
+    trace_id_128_bit: bool,
+    keys: HashMap<LogKey, Option<String>>,
+    span_fields: bool,
+    span_list: bool,
+    redactor: Option<Redactor>,
+}
+
+impl DatadogFormatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits `dd.service` on every log line.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Emits `dd.env` on every log line.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.env = Some(env.into());
+        self
+    }
+
+    /// Emits `dd.version` on every log line.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Writes trace IDs with their upper 64 bits set as 32 hex characters.
+    pub fn with_128_bit_trace_ids(mut self, enabled: bool) -> Self {
+        self.trace_id_128_bit = enabled;
+        self
+    }
+
+    /// Writes a standard attribute under another key, e.g. [`LogKey::Status`] as `level`.
+    pub fn with_key<T: Into<String>>(mut self, key: LogKey, name: T) -> Self {
+        self.keys.insert(key, Some(name.into()));
+        self
+    }
+
+    /// Leaves a standard attribute out of the log lines.
+    pub fn without_key(mut self, key: LogKey) -> Self {
+        self.keys.insert(key, None);
+        self
+    }
+
+    /// Adds the fields of the current span and its parents to the line. The fields of the
+    /// event take precedence over those of the spans, and the fields of a span over those
+    /// of its parents. Span fields named like a key the formatter writes, such as `status`
+    /// or `dd.trace_id`, are left out.
+    pub fn with_span_fields(mut self, enabled: bool) -> Self {
+        self.span_fields = enabled;
+        self
+    }
+
+    /// Adds the current span and its parents, from the root, as a `spans` list of objects
+    /// holding the `name` and the fields of each span.
+    pub fn with_span_list(mut self, enabled: bool) -> Self {
+        self.span_list = enabled;
+        self
+    }
+
+    /// Redacts sensitive values from the fields of the events and spans.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    fn key(&self, key: LogKey) -> Option<&str> {
+        match self.keys.get(&key) {
+            Some(name) => name.as_deref(),
+            None => Some(key.default_name()),
+        }
+    }
+
+    /// Whether the formatter writes `name` itself, so that a span field of that name
+    /// would duplicate the key.
+    fn is_own_key(&self, name: &str) -> bool {
+        matches!(
+            name,
+            "message"
+                | "spans"
+                | "dd.service"
+                | "dd.env"
+                | "dd.version"
+                | "dd.trace_id"
+                | "dd.span_id"
+        ) || LogKey::ALL
+            .into_iter()
+            .any(|key| self.key(key) == Some(name))
+    }
+}
+
+type JsonMap = serde_json::Map<String, serde_json::Value>;
+
+/// The fields recorded on a span, empty unless they were formatted as JSON.
+fn span_fields<S, N>(span: &SpanRef<'_, S>) -> JsonMap
+where
+    S: for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    span.extensions()
+        .get::<FormattedFields<N>>()
+        .and_then(|fields| serde_json::from_str(fields).ok())
+        .unwrap_or_default()
+}
+
+/// A span of the `spans` list.
+#[derive(Serialize)]
+struct SpanEntry<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    fields: &'a JsonMap,
+}
+
+fn status(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "error",
+        Level::WARN => "warn",
+        Level::INFO => "info",
+        Level::DEBUG => "debug",
+        Level::TRACE => "trace",
+    }
+}
+
+/// The `error` field of an event, written as the `error.*` standard attributes.
+#[derive(Default)]
+struct ErrorField {
+    message: String,
+    stack: Option<String>,
+}
+
+/// Serializes the fields of an event, except for `error` and `error.type` which are kept
+/// aside, redacting them on the way when there is a [`Redactor`].
+struct FieldVisitor<'a, S: SerializeMap> {
+    fields: SerdeMapVisitor<S>,
+    error: Option<ErrorField>,
+    error_kind: Option<String>,
+    redactor: Option<&'a Redactor>,
+}
+
+impl<S: SerializeMap> FieldVisitor<'_, S> {
+    fn redacts_key(&self, field: &Field) -> bool {
+        self.redactor.is_some_and(|it| it.redacts_key(field.name()))
+    }
+
+    fn redact(&self, value: String) -> String {
+        match self.redactor {
+            Some(redactor) => redactor.redact(&value).into_owned(),
+            None => value,
+        }
+    }
+}
+
+impl<S: SerializeMap> Visit for FieldVisitor<'_, S> {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if self.redacts_key(field) {
+            self.fields.record_str(field, REDACTED);
+        } else {
+            self.fields.record_bool(field, value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if self.redacts_key(field) {
+            self.fields.record_str(field, REDACTED);
+        } else {
+            self.fields.record_u64(field, value);
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if self.redacts_key(field) {
+            self.fields.record_str(field, REDACTED);
+        } else {
+            self.fields.record_i64(field, value);
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if self.redacts_key(field) {
+            self.fields.record_str(field, REDACTED);
+        } else {
+            self.fields.record_f64(field, value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "error" {
+            self.error = Some(ErrorField {
+                message: self.redact(value.to_string()),
+                ..ErrorField::default()
+            });
+        } else if field.name() == ERROR_TYPE {
+            self.error_kind = Some(self.redact(value.to_string()));
+        } else if let Some(redactor) = self.redactor {
+            self.fields
+                .record_str(field, &redactor.redact_field(field.name(), value));
+        } else {
+            self.fields.record_str(field, value);
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        if field.name() == "error" {
+            self.error = Some(ErrorField {
+                message: self.redact(value.to_string()),
+                stack: Some(self.redact(crate::error::error_stack(value, &Backtrace::disabled()))),
+            });
+        } else if self.redactor.is_some() {
+            self.record_str(field, &value.to_string());
+        } else {
+            self.fields.record_error(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "error" {
+            self.error = Some(ErrorField {
+                message: self.redact(format!("{value:?}")),
+                ..ErrorField::default()
+            });
+        } else if field.name() == ERROR_TYPE {
+            self.error_kind = Some(self.redact(format!("{value:?}")));
+        } else if self.redactor.is_some() {
+            self.record_str(field, &format!("{value:?}"));
+        } else {
+            self.fields.record_debug(field, value);
+        }
+    }
+}
 
 impl<S, N> FormatEvent<S, N> for DatadogFormatter
 where
@@ -99,24 +461,120 @@ where
     {
         let meta = event.metadata();
 
+        // from the root to the current span
+        let spans: Vec<(&'static str, JsonMap)> = match ctx.lookup_current() {
+            Some(span) if self.span_fields || self.span_list => span
+                .scope()
+                .from_root()
+                .map(|span| {
+                    let mut fields = span_fields::<S, N>(&span);
+                    if let Some(redactor) = &self.redactor {
+                        for (key, value) in &mut fields {
+                            redactor.redact_json(key, value);
+                        }
+                    }
+                    (span.name(), fields)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
         let mut visit = || {
             let mut serializer = serde_json::Serializer::new(WriteAdaptor::new(&mut writer));
             let mut serializer = serializer.serialize_map(None)?;
-            serializer.serialize_entry("timestamp", &Utc::now().to_rfc3339())?;
-            serializer.serialize_entry("level", &meta.level().as_serde())?;
-            serializer.serialize_entry("target", meta.target())?;
+            if let Some(key) = self.key(LogKey::Timestamp) {
+                serializer.serialize_entry(key, &Utc::now().to_rfc3339())?;
+            }
+            if let Some(key) = self.key(LogKey::Status) {
+                serializer.serialize_entry(key, status(meta.level()))?;
+            }
+            if let Some(key) = self.key(LogKey::LoggerName) {
+                serializer.serialize_entry(key, meta.target())?;
+            }
+            if let Some(key) = self.key(LogKey::ThreadName)
+                && let Some(name) = std::thread::current().name()
+            {
+                serializer.serialize_entry(key, name)?;
+            }
+            if let Some(key) = self.key(LogKey::MethodName)
+                && let Some(span) = ctx.lookup_current()
+            {
+                serializer.serialize_entry(key, span.name())?;
+            }
+            if let Some(key) = self.key(LogKey::FileName)
+                && let Some(file) = meta.file()
+            {
+                serializer.serialize_entry(key, file)?;
+            }
+            if let Some(key) = self.key(LogKey::LineNumber)
+                && let Some(line) = meta.line()
+            {
+                serializer.serialize_entry(key, &line)?;
+            }
+            if let Some(service) = &self.service {
+                serializer.serialize_entry("dd.service", service)?;
+            }
+            if let Some(env) = &self.env {
+                serializer.serialize_entry("dd.env", env)?;
+            }
+            if let Some(version) = &self.version {
+                serializer.serialize_entry("dd.version", version)?;
+            }
 
             // fields -> stolen from https://github.com/tokio-rs/tracing/blob/tracing-subscriber-0.3.17/tracing-subscriber/src/fmt/format/json.rs#L263-L268
-            let mut visitor = tracing_serde::SerdeMapVisitor::new(serializer);
+            let mut visitor = FieldVisitor {
+                fields: SerdeMapVisitor::new(serializer),
+                error: None,
+                error_kind: None,
+                redactor: self.redactor.as_ref(),
+            };
             event.record(&mut visitor);
-            serializer = visitor.take_serializer()?;
+            serializer = visitor.fields.take_serializer()?;
+
+            if let Some(key) = self.key(LogKey::ErrorKind)
+                && let Some(kind) = &visitor.error_kind
+            {
+                serializer.serialize_entry(key, kind)?;
+            }
+            if let Some(error) = visitor.error {
+                if let Some(key) = self.key(LogKey::ErrorMessage) {
+                    serializer.serialize_entry(key, &error.message)?;
+                }
+                if let Some(key) = self.key(LogKey::ErrorStack)
+                    && let Some(stack) = &error.stack
+                {
+                    serializer.serialize_entry(key, stack)?;
+                }
+            }
 
-            if let Some(ref span_ref) = ctx.lookup_current() {
-                if let Some(trace_info) = lookup_trace_info(span_ref) {
-                    serializer.serialize_entry("dd.span_id", &trace_info.span_id)?;
-                    serializer.serialize_entry("dd.trace_id", &trace_info.trace_id)?;
+            if self.span_fields {
+                let mut flattened = JsonMap::new();
+                for (_, fields) in &spans {
+                    flattened.extend(fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+                for (key, value) in flattened.iter().filter(|(key, _)| {
+                    meta.fields().field(key.as_str()).is_none() && !self.is_own_key(key)
+                }) {
+                    serializer.serialize_entry(key, value)?;
                 }
             }
+            if self.span_list && !spans.is_empty() {
+                let list: Vec<_> = spans
+                    .iter()
+                    .map(|(name, fields)| SpanEntry { name, fields })
+                    .collect();
+                serializer.serialize_entry("spans", &list)?;
+            }
+
+            if let Some(ref span_ref) = ctx.lookup_current()
+                && let Some(trace_info) = lookup_trace_info(span_ref)
+            {
+                serializer.serialize_entry("dd.span_id", &trace_info.span_id)?;
+                serializer.serialize_entry(
+                    "dd.trace_id",
+                    &DatadogTraceId::new(trace_info.trace_id, self.trace_id_128_bit),
+                )?;
+            }
 
             serializer.end()
         };
@@ -126,6 +584,68 @@ where
     }
 }
 
+/// Appends `dd.trace_id` and `dd.span_id` to the first line of the events formatted by a
+/// human readable format, such as the [`Format`](tracing_subscriber::fmt::format::Format)
+/// of `full`, `compact` or `pretty` logs.
+///
+/// The line is formatted into a buffer first, which has no ANSI escapes unless the
+/// wrapped format enables them with `with_ansi`.
+#[derive(Debug, Clone, Default)]
+pub struct CorrelatedFormat<F> {
+    inner: F,
+    trace_id_128_bit: bool,
+}
+
+impl<F> CorrelatedFormat<F> {
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            trace_id_128_bit: false,
+        }
+    }
+
+    /// Writes trace IDs with their upper 64 bits set as 32 hex characters.
+    pub fn with_128_bit_trace_ids(mut self, enabled: bool) -> Self {
+        self.trace_id_128_bit = enabled;
+        self
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for CorrelatedFormat<F>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let Some(trace_info) = ctx
+            .lookup_current()
+            .and_then(|span| lookup_trace_info(&span))
+        else {
+            return self.inner.format_event(ctx, writer, event);
+        };
+
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        let end = line.find('\n').unwrap_or(line.len());
+        line.insert_str(
+            end,
+            &format!(
+                " dd.trace_id={} dd.span_id={}",
+                DatadogTraceId::new(trace_info.trace_id, self.trace_id_128_bit),
+                trace_info.span_id.0
+            ),
+        );
+        writer.write_str(&line)
+    }
+}
+
 struct WriteAdaptor<'a> {
     fmt_write: &'a mut dyn std::fmt::Write,
 }
@@ -153,8 +673,262 @@ impl io::Write for WriteAdaptor<'_> {
 
 #[cfg(test)]
 mod tests {
-    use super::DatadogId;
-    use opentelemetry::trace::{SpanId, TraceId};
+    use super::{CorrelatedFormat, DatadogFormatter, DatadogId, DatadogTraceId, LogKey};
+    use crate::redaction::{REDACTED, Redactor};
+    use opentelemetry::trace::{SpanId, TraceId, TracerProvider as _};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::MakeWriter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct CapturedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for CapturedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for CapturedWriter {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn format_event(formatter: DatadogFormatter, emit: impl FnOnce()) -> serde_json::Value {
+        let writer = CapturedWriter::default();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .event_format(formatter)
+                .with_writer(writer.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, emit);
+
+        let output = writer.0.lock().unwrap();
+        serde_json::from_slice(&output).unwrap()
+    }
+
+    #[test]
+    fn test_unified_service_tags_in_log_line() {
+        let formatter = DatadogFormatter::new()
+            .with_service("billing")
+            .with_env("prod")
+            .with_version("1.2.3");
+        let line = format_event(formatter, || tracing::info!(answer = 42, "hello"));
+
+        assert_eq!(line["dd.service"], "billing");
+        assert_eq!(line["dd.env"], "prod");
+        assert_eq!(line["dd.version"], "1.2.3");
+        assert_eq!(line["message"], "hello");
+        assert_eq!(line["answer"], 42);
+    }
+
+    #[test]
+    fn test_standard_attributes_in_log_line() {
+        let line = format_event(DatadogFormatter::new(), || {
+            let _span = tracing::info_span!("charge").entered();
+            tracing::warn!("declined");
+        });
+
+        assert_eq!(line["status"], "warn");
+        assert_eq!(line["logger.name"], module_path!());
+        assert_eq!(line["logger.method_name"], "charge");
+        assert_eq!(line["logger.file_name"], file!());
+        assert!(line["logger.line_number"].is_u64());
+        assert!(line["logger.thread_name"].is_string());
+        assert!(line.get("level").is_none());
+    }
+
+    #[test]
+    fn test_error_field_as_error_attributes() {
+        let line = format_event(DatadogFormatter::new(), || {
+            let err = std::io::Error::other("timed out");
+            tracing::error!(error = &err as &dyn std::error::Error, "charge failed");
+        });
+
+        // the type behind a `dyn Error` is unknown
+        assert!(line.get("error.kind").is_none());
+        assert_eq!(line["error.message"], "timed out");
+        assert_eq!(line["error.stack"], "timed out");
+        assert!(line.get("error").is_none());
+
+        let line = format_event(DatadogFormatter::new(), || {
+            let err = std::io::Error::other("timed out");
+            tracing::error!(
+                error.type = "std::io::Error",
+                error = &err as &dyn std::error::Error,
+                "charge failed"
+            );
+        });
+
+        assert_eq!(line["error.kind"], "std::io::Error");
+        assert!(line.get("error.type").is_none());
+
+        let line = format_event(DatadogFormatter::new(), || {
+            tracing::error!(error = "card declined", "charge failed");
+        });
+
+        assert_eq!(line["error.message"], "card declined");
+        assert!(line.get("error.kind").is_none());
+    }
+
+    #[test]
+    fn test_renamed_and_omitted_keys() {
+        let formatter = DatadogFormatter::new()
+            .with_key(LogKey::Status, "level")
+            .with_key(LogKey::LoggerName, "target")
+            .without_key(LogKey::ThreadName)
+            .without_key(LogKey::ErrorMessage);
+        let line = format_event(formatter, || tracing::error!(error = "declined", "hello"));
+
+        assert_eq!(line["level"], "error");
+        assert_eq!(line["target"], module_path!());
+        assert!(line.get("status").is_none());
+        assert!(line.get("logger.name").is_none());
+        assert!(line.get("logger.thread_name").is_none());
+        assert!(line.get("error.message").is_none());
+        assert_eq!("logger.name".parse(), Ok(LogKey::LoggerName));
+    }
+
+    #[test]
+    fn test_span_fields_flattened_with_precedence() {
+        let formatter = DatadogFormatter::new().with_span_fields(true);
+        let line = format_event(formatter, || {
+            let _outer = tracing::info_span!("request", user_id = 1, region = "eu").entered();
+            let _inner = tracing::info_span!("charge", user_id = 2, amount = 42).entered();
+            tracing::info!(region = "us", "charged");
+        });
+
+        assert_eq!(line["user_id"], 2);
+        assert_eq!(line["amount"], 42);
+        assert_eq!(line["region"], "us");
+        assert!(line.get("spans").is_none());
+    }
+
+    #[test]
+    fn test_span_fields_skip_own_keys() {
+        let formatter = DatadogFormatter::new()
+            .with_key(LogKey::Status, "level")
+            .with_span_fields(true);
+        let line = format_event(formatter, || {
+            let _span = tracing::info_span!(
+                "charge",
+                level = "premium",
+                dd.trace_id = "forged",
+                timestamp = 0,
+                amount = 42,
+            )
+            .entered();
+            tracing::warn!("declined");
+        });
+
+        assert_eq!(line["level"], "warn");
+        assert_ne!(line["dd.trace_id"], "forged");
+        assert!(line["timestamp"].is_string());
+        assert_eq!(line["amount"], 42);
+    }
+
+    #[test]
+    fn test_span_list_from_root() {
+        let formatter = DatadogFormatter::new().with_span_list(true);
+        let line = format_event(formatter, || {
+            let _outer = tracing::info_span!("request", user_id = 1).entered();
+            let _inner = tracing::info_span!("charge").entered();
+            tracing::info!("charged");
+        });
+
+        assert_eq!(
+            line["spans"],
+            serde_json::json!([
+                {"name": "request", "user_id": 1},
+                {"name": "charge"},
+            ])
+        );
+        assert!(line.get("user_id").is_none());
+
+        let line = format_event(DatadogFormatter::new().with_span_list(true), || {
+            tracing::info!("outside of a span");
+        });
+        assert!(line.get("spans").is_none());
+    }
+
+    #[test]
+    fn test_redacted_fields_in_log_line() {
+        let formatter = DatadogFormatter::new()
+            .with_span_fields(true)
+            .with_redactor(Redactor::new().with_defaults());
+        let line = format_event(formatter, || {
+            let _span = tracing::info_span!("login", user.email = "jane@example.com").entered();
+            tracing::error!(
+                password = "hunter2",
+                token = 42,
+                headers = ?["Authorization: Bearer abc.def"],
+                error = "invalid password for jane@example.com",
+                "login failed for jane@example.com"
+            );
+        });
+
+        assert_eq!(line["message"], "login failed for [REDACTED]");
+        assert_eq!(line["password"], REDACTED);
+        assert_eq!(line["token"], REDACTED);
+        assert_eq!(line["headers"], r#"["Authorization: Bearer [REDACTED]"]"#);
+        assert_eq!(line["error.message"], "invalid password for [REDACTED]");
+        assert_eq!(line["user.email"], REDACTED);
+    }
+
+    #[test]
+    fn test_text_lines_correlated_with_traces() {
+        let writer = CapturedWriter::default();
+        let provider = SdkTracerProvider::builder().build();
+        let format = tracing_subscriber::fmt::format()
+            .compact()
+            .without_time()
+            .with_ansi(false);
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(CorrelatedFormat::new(format))
+                    .with_writer(writer.clone()),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside");
+            tracing::info_span!("charge").in_scope(|| tracing::info!(amount = 42, "charged"));
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(!lines[0].contains("dd.trace_id"));
+        assert!(lines[1].contains("charged amount=42"));
+        let ids: Vec<_> = lines[1]
+            .split(' ')
+            .filter_map(|it| it.split_once('='))
+            .filter(|(key, _)| key.starts_with("dd."))
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids[0].1.parse::<u64>().unwrap() > 0);
+        assert!(ids[1].1.parse::<u64>().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_unified_service_tags_omitted_when_unset() {
+        let line = format_event(DatadogFormatter::new(), || tracing::info!("hello"));
+
+        assert!(line.get("dd.service").is_none());
+        assert!(line.get("dd.env").is_none());
+    }
 
     #[test]
     fn test_trace_id_converted_to_datadog_id() {
@@ -172,6 +946,25 @@ mod tests {
         assert_eq!(datadog_id.0, 0);
     }
 
+    #[test]
+    fn test_128_bit_trace_id_serialized_as_hex() {
+        let trace_id = TraceId::from_hex("6553a5d800000000c7ba048b78f7a9fb").unwrap();
+
+        let full = serde_json::to_value(DatadogTraceId::new(trace_id, true)).unwrap();
+        let low = serde_json::to_value(DatadogTraceId::new(trace_id, false)).unwrap();
+
+        assert_eq!(full, "6553a5d800000000c7ba048b78f7a9fb");
+        assert_eq!(low, 14391820556292303355u64);
+    }
+
+    #[test]
+    fn test_64_bit_trace_id_serialized_as_number() {
+        let trace_id = TraceId::from_hex("0000000000000000c7ba048b78f7a9fb").unwrap();
+        let value = serde_json::to_value(DatadogTraceId::new(trace_id, true)).unwrap();
+
+        assert_eq!(value, 14391820556292303355u64);
+    }
+
     #[test]
     fn test_span_id_converted_to_datadog_id() {
         let span_id = SpanId::from_hex("58406520a0066491").unwrap();
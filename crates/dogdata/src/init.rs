@@ -20,88 +20,446 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::formatter::DatadogFormatter;
+use crate::appender::{self, DroppedLinesReporter};
+use crate::config::{DogdataConfig, LogFormat, LogOutput};
+use crate::error::ErrorEventLayer;
+use crate::formatter::{CorrelatedFormat, DatadogFormatter};
+use crate::log_level::LogLevelHandle;
+use crate::metrics::{self, MetricsClient};
 use crate::model::{default_name_mapping, default_resource_mapping, default_service_name_mapping};
+use crate::panic;
+use crate::runtime::{self, RuntimeMetricsReporter};
 use crate::shutdown::TracerShutdown;
-use crate::tracer::build_tracer;
+use crate::tracer::{install_globals, tracer, tracer_provider};
 use opentelemetry::trace::TraceError;
 use opentelemetry_datadog::FieldMappingFn;
-use std::env;
-use tracing::Subscriber;
-use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tracing::{Dispatch, Subscriber};
+use tracing_appender::non_blocking::{ErrorCounter, NonBlocking, NonBlockingBuilder, WorkerGuard};
+use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry::LookupSpan;
-use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::{EnvFilter, Layer, Registry};
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+use tracing_subscriber::{EnvFilter, Layer, Registry, reload};
 
-fn loglevel_filter_layer(_dd_enabled: bool) -> EnvFilter {
-    let log_level = env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
-
-    // `otel::setup` set to debug to log detected resources, configuration read and infered
-    let otel_log_level = env::var("OTEL_LOG_LEVEL").unwrap_or_else(|_| "debug".to_string());
+fn loglevel_filter_layer(config: &DogdataConfig) -> EnvFilter {
+    EnvFilter::builder().parse_lossy(config.filter_directives())
+}
 
-    unsafe {
-        env::set_var("RUST_LOG", format!("{log_level},otel={otel_log_level}"));
+fn datadog_formatter(config: &DogdataConfig) -> DatadogFormatter {
+    let mut formatter = DatadogFormatter::new()
+        .with_128_bit_trace_ids(config.trace_id_128_bit())
+        .with_span_fields(config.log_span_fields())
+        .with_span_list(config.log_span_list());
+    if let Some(service) = config.service() {
+        formatter = formatter.with_service(service);
+    }
+    if let Some(env) = config.env() {
+        formatter = formatter.with_env(env);
+    }
+    if let Some(version) = config.version() {
+        formatter = formatter.with_version(version);
+    }
+    if let Some(redactor) = config.redactor() {
+        formatter = formatter.with_redactor(redactor.clone());
     }
+    for (key, name) in config.log_keys() {
+        formatter = match name {
+            Some(name) => formatter.with_key(*key, name.clone()),
+            None => formatter.without_key(*key),
+        };
+    }
+    formatter
+}
 
-    EnvFilter::from_default_env()
+fn correlated_format<F>(config: &DogdataConfig, format: F) -> CorrelatedFormat<F> {
+    CorrelatedFormat::new(format).with_128_bit_trace_ids(config.trace_id_128_bit())
 }
 
 fn log_layer<S>(
-    dd_enabled: bool,
+    config: &DogdataConfig,
     non_blocking: NonBlocking,
 ) -> Box<dyn Layer<S> + Send + Sync + 'static>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    if dd_enabled {
-        Box::new(
+    let ansi = !matches!(config.log_output(), LogOutput::File(_));
+    let format = tracing_subscriber::fmt::format().with_ansi(ansi);
+    match config.log_format() {
+        LogFormat::Json => Box::new(
             tracing_subscriber::fmt::layer()
                 .json()
-                .event_format(DatadogFormatter)
+                .event_format(datadog_formatter(config))
                 .with_writer(non_blocking),
-        )
-    } else {
-        Box::new(tracing_subscriber::fmt::layer().with_writer(non_blocking))
+        ),
+        LogFormat::Full => Box::new(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(ansi)
+                .event_format(correlated_format(config, format))
+                .with_writer(non_blocking),
+        ),
+        LogFormat::Pretty => Box::new(
+            tracing_subscriber::fmt::layer()
+                .pretty()
+                .with_ansi(ansi)
+                .event_format(correlated_format(config, format.pretty()))
+                .with_writer(non_blocking),
+        ),
+        LogFormat::Compact => Box::new(
+            tracing_subscriber::fmt::layer()
+                .compact()
+                .with_ansi(ansi)
+                .event_format(correlated_format(config, format.compact()))
+                .with_writer(non_blocking),
+        ),
     }
 }
 
-pub fn init(mappings: Option<ModelMappings>) -> Result<(WorkerGuard, TracerShutdown), TraceError> {
-    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+/// The layers and guards that make up the dogdata subscriber.
+///
+/// Returned by [`layers`] so they can be composed with other layers, or installed on a
+/// scoped subscriber instead of the global one. All layers are built for the same base
+/// subscriber, so they are chained with [`Layer::and_then`]:
+///
+/// ```no_run
+/// use dogdata::DogdataConfig;
+/// use tracing_subscriber::Layer;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let layers = dogdata::layers(&DogdataConfig::from_env()).unwrap();
+/// let subscriber = tracing_subscriber::registry()
+///     .with(
+///         layers
+///             .filter
+///             .and_then(layers.log)
+///             .and_then(layers.telemetry)
+///             .and_then(layers.error_events),
+///     )
+///     // add your own layers here
+///     .with(tracing_subscriber::fmt::layer().pretty());
+///
+/// tracing::subscriber::with_default(subscriber, || {
+///     tracing::info!("only visible in this scope");
+/// });
+/// # layers.shutdown.shutdown();
+/// ```
+///
+/// The `guard` must be kept alive for as long as logs should be written, and `shutdown`
+/// should be called before exiting to flush pending spans. The directives of the `filter`
+/// can be changed at runtime through the `log_level` handle. When the layers make up the
+/// global subscriber, install the `globals` too, as [`init`] does.
+pub struct DogdataLayers<S> {
+    pub filter: reload::Layer<EnvFilter, S>,
+    pub log: Box<dyn Layer<S> + Send + Sync + 'static>,
+    pub telemetry: Option<OpenTelemetryLayer<S, Tracer>>,
+    pub error_events: Option<ErrorEventLayer>,
+    pub guard: WorkerGuard,
+    pub shutdown: TracerShutdown,
+    pub log_level: LogLevelHandle,
+    pub globals: DogdataGlobals,
+}
 
-    let dd_enabled = env::var("DD_ENABLED").map(|s| s == "true").unwrap_or(false);
+/// Errors returned by [`try_init`].
+#[derive(Debug)]
+pub enum InitError {
+    /// The tracer provider could not be built.
+    Trace(TraceError),
+    /// A global subscriber has already been installed.
+    SubscriberInit(TryInitError),
+}
 
-    let (telemetry_layer, provider) = if dd_enabled {
-        let (tracer, provider) = build_tracer(mappings)?;
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Trace(err) => write!(f, "failed to build the tracer: {err}"),
+            InitError::SubscriberInit(err) => {
+                write!(f, "failed to install the global subscriber: {err}")
+            }
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InitError::Trace(err) => Some(err),
+            InitError::SubscriberInit(err) => Some(err),
+        }
+    }
+}
+
+impl From<TraceError> for InitError {
+    fn from(err: TraceError) -> Self {
+        InitError::Trace(err)
+    }
+}
+
+impl From<TryInitError> for InitError {
+    fn from(err: TryInitError) -> Self {
+        InitError::SubscriberInit(err)
+    }
+}
+
+/// Builds the filter, log and OpenTelemetry layers without installing them.
+///
+/// No global state is touched: the tracer provider, propagator, metrics client and
+/// background reporters are returned in [`DogdataLayers::globals`], for [`init`] to
+/// install.
+pub fn layers<S>(config: &DogdataConfig) -> Result<DogdataLayers<S>, TraceError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let writer = appender::log_writer(config)
+        .map_err(|err| format!("failed to open the log output: {err}"))?;
+    let (non_blocking, guard) = NonBlockingBuilder::default()
+        .buffered_lines_limit(config.log_buffered_lines)
+        .lossy(config.log_lossy)
+        .finish(writer);
+    let error_counter = config.log_lossy.then(|| non_blocking.error_counter());
+
+    let (telemetry, provider) = if config.enabled || config.console_exporter.is_some() {
+        let provider = tracer_provider(config)?;
         (
-            Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+            Some(tracing_opentelemetry::layer().with_tracer(tracer(&provider))),
             Some(provider),
         )
     } else {
         (None, None)
     };
 
-    Registry::default()
-        .with(loglevel_filter_layer(dd_enabled))
-        .with(log_layer(dd_enabled, non_blocking))
-        .with(telemetry_layer)
-        .init();
+    let (filter, handle) = reload::Layer::new(loglevel_filter_layer(config));
 
-    Ok((guard, TracerShutdown::new(provider)))
+    Ok(DogdataLayers {
+        filter,
+        log: log_layer(config, non_blocking),
+        telemetry,
+        error_events: config.error_events_enabled.then(ErrorEventLayer::new),
+        guard,
+        shutdown: TracerShutdown::new(provider.clone()),
+        log_level: LogLevelHandle::new(handle, &config.log_directives, &config.otel_log_level),
+        globals: DogdataGlobals {
+            config: config.clone(),
+            provider,
+            error_counter,
+        },
+    })
 }
 
+/// The process-wide state that goes with the dogdata layers: the global tracer provider
+/// and propagator, the global [`MetricsClient`], and the runtime metrics and dropped log
+/// lines reporters.
+///
+/// Kept apart from the layers so that building them has no side effects. [`init`] and
+/// [`try_init`] install it along with the global subscriber.
+pub struct DogdataGlobals {
+    config: DogdataConfig,
+    provider: Option<SdkTracerProvider>,
+    error_counter: Option<ErrorCounter>,
+}
+
+impl DogdataGlobals {
+    /// Installs the global state and starts the reporters, which are stopped by the
+    /// returned [`TracerShutdown`]. `shutdown` is shut down if a reporter fails to start.
+    pub fn install(self, shutdown: TracerShutdown) -> Result<TracerShutdown, TraceError> {
+        Ok(self.start(shutdown)?.install())
+    }
+
+    /// Starts the reporters and creates the metrics client, without installing anything.
+    /// Shuts `shutdown` down on failure.
+    fn start(self, mut shutdown: TracerShutdown) -> Result<StartedGlobals, TraceError> {
+        let fail = |shutdown: TracerShutdown, err: String| {
+            shutdown.shutdown();
+            Err(TraceError::from(err))
+        };
+        let config = &self.config;
+
+        if let Some(counter) = self.error_counter {
+            match DroppedLinesReporter::start(counter, appender::DROPPED_LINES_INTERVAL) {
+                Ok(reporter) => shutdown = shutdown.with_dropped_lines(Some(reporter)),
+                Err(err) => {
+                    let err = format!("failed to start the dropped log lines reporter: {err}");
+                    return fail(shutdown, err);
+                }
+            }
+        }
+
+        let mut client = None;
+        if config.enabled {
+            let metrics = match MetricsClient::from_config(config) {
+                Ok(metrics) => metrics,
+                Err(err) => {
+                    return fail(
+                        shutdown,
+                        format!("failed to create the metrics client: {err}"),
+                    );
+                }
+            };
+            if let (true, Ok(handle)) = (config.runtime_metrics_enabled, Handle::try_current()) {
+                let reporter = RuntimeMetricsReporter::start(
+                    handle,
+                    metrics.clone(),
+                    runtime::DEFAULT_INTERVAL,
+                );
+                match reporter {
+                    Ok(reporter) => shutdown = shutdown.with_runtime_metrics(Some(reporter)),
+                    Err(err) => {
+                        return fail(
+                            shutdown,
+                            format!("failed to start the runtime metrics: {err}"),
+                        );
+                    }
+                }
+            }
+            client = Some(metrics);
+        }
+
+        Ok(StartedGlobals {
+            config: self.config,
+            provider: self.provider,
+            client,
+            shutdown,
+        })
+    }
+}
+
+/// The [`DogdataGlobals`] whose reporters are running, ready to be installed.
+struct StartedGlobals {
+    config: DogdataConfig,
+    provider: Option<SdkTracerProvider>,
+    client: Option<MetricsClient>,
+    shutdown: TracerShutdown,
+}
+
+impl StartedGlobals {
+    /// Sets the global tracer provider, propagator and metrics client, which cannot fail.
+    fn install(self) -> TracerShutdown {
+        if let Some(provider) = &self.provider {
+            install_globals(&self.config, provider);
+        }
+        if let Some(client) = self.client {
+            metrics::set_global_client(client);
+        }
+        self.shutdown
+    }
+}
+
+/// Builds the layers and installs them with `set_global`, along with their globals.
+///
+/// The reporters are started before the subscriber is installed, and the globals are set
+/// after it, so that nothing is left half-installed when either step fails.
+fn install<E>(
+    config: DogdataConfig,
+    set_global: impl FnOnce(Dispatch) -> Result<(), E>,
+) -> Result<(WorkerGuard, TracerShutdown, LogLevelHandle), E>
+where
+    E: From<TraceError>,
+{
+    let layers = layers(&config)?;
+    let globals = layers.globals.start(layers.shutdown)?;
+
+    let subscriber = Registry::default().with(
+        layers
+            .filter
+            .and_then(layers.log)
+            .and_then(layers.telemetry)
+            .and_then(layers.error_events),
+    );
+    if let Err(err) = set_global(Dispatch::new(subscriber)) {
+        globals.shutdown.shutdown();
+        return Err(err);
+    }
+
+    let shutdown = globals.install();
+    if config.panic_hook_enabled {
+        panic::install_hook(&shutdown);
+    }
+
+    Ok((layers.guard, shutdown, layers.log_level))
+}
+
+/// Installs the dogdata layers as the global subscriber.
+///
+/// Returns the guard of the log writer, the handle to shut the tracer down, and the
+/// [`LogLevelHandle`] to change the log directives at runtime.
+///
+/// # Panics
+///
+/// Panics if a global subscriber has already been installed. Use [`try_init`] to get an
+/// error instead.
+pub fn init(
+    config: DogdataConfig,
+) -> Result<(WorkerGuard, TracerShutdown, LogLevelHandle), TraceError> {
+    install(config, |dispatch| {
+        dispatch.init();
+        Ok(())
+    })
+}
+
+/// Installs the dogdata layers as the global subscriber, returning an error if one has
+/// already been installed.
+pub fn try_init(
+    config: DogdataConfig,
+) -> Result<(WorkerGuard, TracerShutdown, LogLevelHandle), InitError> {
+    install(config, |dispatch| {
+        dispatch.try_init().map_err(InitError::from)
+    })
+}
+
+#[derive(Clone)]
 pub struct ModelMappings {
-    pub service_name_mapping: Option<Box<FieldMappingFn>>,
-    pub name_mapping: Option<Box<FieldMappingFn>>,
-    pub resource_mapping: Option<Box<FieldMappingFn>>,
+    pub service_name_mapping: Option<Arc<FieldMappingFn>>,
+    pub name_mapping: Option<Arc<FieldMappingFn>>,
+    pub resource_mapping: Option<Arc<FieldMappingFn>>,
 }
 
 impl Default for ModelMappings {
     fn default() -> Self {
         Self {
-            service_name_mapping: Some(Box::new(default_service_name_mapping)),
-            name_mapping: Some(Box::new(default_name_mapping)),
-            resource_mapping: Some(Box::new(default_resource_mapping)),
+            service_name_mapping: Some(Arc::new(default_service_name_mapping)),
+            name_mapping: Some(Arc::new(default_name_mapping)),
+            resource_mapping: Some(Arc::new(default_resource_mapping)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers_compose_into_scoped_subscriber() {
+        let layers = layers(&DogdataConfig::default()).unwrap();
+        assert!(layers.telemetry.is_none());
+
+        let subscriber = Registry::default().with(
+            layers
+                .filter
+                .and_then(layers.log)
+                .and_then(layers.telemetry)
+                .and_then(layers.error_events),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("scoped");
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_layers_leave_global_state_untouched() {
+        let config = DogdataConfig::default()
+            .with_enabled(true)
+            .with_service("test-service")
+            .with_runtime_metrics(true);
+
+        for _ in 0..2 {
+            let layers = layers::<Registry>(&config).unwrap();
+            assert!(layers.telemetry.is_some());
+            layers.shutdown.shutdown();
         }
+        assert!(metrics::global_client().is_none());
     }
 }
@@ -23,13 +23,28 @@
 //! Utilities to integrate Rust services with Datadog using [`opentelemetry`],
 //! [`tracing`], and other open source libraries.
 
+pub mod agent;
+mod appender;
+pub mod baggage;
+pub mod config;
+pub mod error;
+mod exporter;
 pub mod formatter;
 pub mod init;
+pub mod log_level;
+pub mod metrics;
 pub mod model;
+pub mod panic;
+pub mod propagator;
+pub mod redaction;
+pub mod runtime;
+pub mod sampler;
 pub mod shutdown;
+pub mod trace;
 pub mod tracer;
 
 #[cfg(feature = "axum")]
 pub mod axum;
 
-pub use init::init;
+pub use config::DogdataConfig;
+pub use init::{init, layers, try_init};
@@ -0,0 +1,320 @@
+//! Changing the log level of a running service.
+//!
+//! The filter built from `RUST_LOG` sits behind a [`reload`] layer, so the
+//! [`LogLevelHandle`] returned by [`init`](crate::init) can swap its directives, e.g. to
+//! raise the verbosity during an incident without a redeploy:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! let (_guard, shutdown, log_level) = dogdata::init(dogdata::DogdataConfig::from_env()).unwrap();
+//!
+//! // back to the previous directives in 10 minutes
+//! log_level
+//!     .set_for("debug,hyper=info", Duration::from_secs(600))
+//!     .unwrap();
+//! # shutdown.shutdown();
+//! ```
+//!
+//! With the `axum` feature, [`log_level_router`](crate::axum::log_level_router) exposes the
+//! handle over HTTP.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::reload;
+
+type ReloadFn = dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync;
+
+/// Errors returned when changing the log level.
+#[derive(Debug)]
+pub enum LogLevelError {
+    /// The directives are not valid `RUST_LOG` directives.
+    Parse(ParseError),
+    /// The subscriber holding the filter is gone.
+    Reload(reload::Error),
+    /// The thread reverting the directives could not be started.
+    Revert(io::Error),
+    /// The revert is too far in the future to be scheduled.
+    Delay(Duration),
+}
+
+impl fmt::Display for LogLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevelError::Parse(err) => write!(f, "invalid log directives: {err}"),
+            LogLevelError::Reload(err) => write!(f, "failed to reload the log filter: {err}"),
+            LogLevelError::Revert(err) => {
+                write!(f, "failed to schedule the log level revert: {err}")
+            }
+            LogLevelError::Delay(delay) => {
+                write!(f, "cannot revert the log level in {}s", delay.as_secs())
+            }
+        }
+    }
+}
+
+impl Error for LogLevelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LogLevelError::Parse(err) => Some(err),
+            LogLevelError::Reload(err) => Some(err),
+            LogLevelError::Revert(err) => Some(err),
+            LogLevelError::Delay(_) => None,
+        }
+    }
+}
+
+/// Reads and replaces the log directives of the [`reload`] layer built by
+/// [`layers`](crate::layers).
+///
+/// Directives are those of `RUST_LOG`. The `otel` target keeps the level set by
+/// `OTEL_LOG_LEVEL`.
+#[derive(Clone)]
+pub struct LogLevelHandle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    reload: Box<ReloadFn>,
+    otel_log_level: String,
+    state: Mutex<State>,
+}
+
+struct State {
+    directives: String,
+    /// Incremented on every change, so that a revert does not undo a later change.
+    generation: u64,
+    revert: Option<Revert>,
+}
+
+struct Revert {
+    at: Instant,
+    directives: String,
+}
+
+impl fmt::Debug for LogLevelHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogLevelHandle")
+            .field("directives", &self.directives())
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogLevelHandle {
+    pub(crate) fn new<S>(
+        handle: reload::Handle<EnvFilter, S>,
+        directives: &str,
+        otel_log_level: &str,
+    ) -> Self
+    where
+        S: 'static,
+    {
+        Self {
+            inner: Arc::new(Inner {
+                reload: Box::new(move |filter| handle.reload(filter)),
+                otel_log_level: otel_log_level.to_string(),
+                state: Mutex::new(State {
+                    directives: directives.to_string(),
+                    generation: 0,
+                    revert: None,
+                }),
+            }),
+        }
+    }
+
+    /// The current directives, without the `otel` one.
+    pub fn directives(&self) -> String {
+        self.inner.state().directives.clone()
+    }
+
+    /// How long until the directives set with [`set_for`](Self::set_for) are reverted.
+    pub fn revert_in(&self) -> Option<Duration> {
+        self.inner
+            .state()
+            .revert
+            .as_ref()
+            .map(|it| it.at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Replaces the directives, cancelling any pending revert.
+    pub fn set(&self, directives: &str) -> Result<(), LogLevelError> {
+        let mut state = self.inner.state();
+        self.inner.apply(&mut state, directives)?;
+        Ok(())
+    }
+
+    /// Replaces the directives, then restores the current ones after `duration`, unless
+    /// they are changed again in the meantime. When a revert is already pending, the
+    /// directives it would have restored are restored instead.
+    pub fn set_for(&self, directives: &str, duration: Duration) -> Result<(), LogLevelError> {
+        let at = Instant::now()
+            .checked_add(duration)
+            .ok_or(LogLevelError::Delay(duration))?;
+        let mut state = self.inner.state();
+        let previous = match &state.revert {
+            Some(revert) => revert.directives.clone(),
+            None => state.directives.clone(),
+        };
+        let generation = self.inner.apply(&mut state, directives)?;
+        state.revert = Some(Revert {
+            at,
+            directives: previous.clone(),
+        });
+
+        let inner = self.inner.clone();
+        let directives = previous.clone();
+        let spawned = thread::Builder::new()
+            .name("dogdata-log-level".to_string())
+            .spawn(move || {
+                thread::sleep(duration);
+                let mut state = inner.state();
+                if state.generation == generation {
+                    // the subscriber may be gone by now, leaving nothing to revert
+                    let _ = inner.apply(&mut state, &directives);
+                }
+            });
+        if let Err(err) = spawned {
+            self.inner.apply(&mut state, &previous)?;
+            return Err(LogLevelError::Revert(err));
+        }
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|it| it.into_inner())
+    }
+
+    /// Reloads the filter with the directives, returning the new generation.
+    fn apply(&self, state: &mut State, directives: &str) -> Result<u64, LogLevelError> {
+        let filter = EnvFilter::builder()
+            .parse(filter_directives(directives, &self.otel_log_level))
+            .map_err(LogLevelError::Parse)?;
+        (self.reload)(filter).map_err(LogLevelError::Reload)?;
+
+        state.directives = directives.trim().to_string();
+        state.generation += 1;
+        state.revert = None;
+        Ok(state.generation)
+    }
+}
+
+/// The `RUST_LOG` directives followed by the `otel` one.
+pub(crate) fn filter_directives(directives: &str, otel_log_level: &str) -> String {
+    format!("{},otel={otel_log_level}", directives.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::Registry;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn handle() -> (LogLevelHandle, impl tracing::Subscriber) {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let subscriber = Registry::default().with(filter);
+        (LogLevelHandle::new(handle, "info", "debug"), subscriber)
+    }
+
+    #[test]
+    fn test_set_replaces_directives() {
+        let (log_level, subscriber) = handle();
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!tracing::enabled!(tracing::Level::DEBUG));
+
+            log_level.set("debug").unwrap();
+            assert!(tracing::enabled!(tracing::Level::DEBUG));
+            assert!(tracing::enabled!(target: "otel", tracing::Level::DEBUG));
+            assert_eq!(log_level.directives(), "debug");
+
+            assert!(matches!(
+                log_level.set("info,billing=loud"),
+                Err(LogLevelError::Parse(_))
+            ));
+            assert_eq!(log_level.directives(), "debug");
+        });
+    }
+
+    #[test]
+    fn test_set_for_reverts_after_duration() {
+        let (log_level, subscriber) = handle();
+
+        tracing::subscriber::with_default(subscriber, || {
+            log_level
+                .set_for("trace", Duration::from_millis(50))
+                .unwrap();
+            assert_eq!(log_level.directives(), "trace");
+            assert!(log_level.revert_in().is_some());
+
+            thread::sleep(Duration::from_millis(500));
+            assert_eq!(log_level.directives(), "info");
+            assert_eq!(log_level.revert_in(), None);
+            assert!(!tracing::enabled!(tracing::Level::DEBUG));
+        });
+    }
+
+    #[test]
+    fn test_extended_revert_restores_original_directives() {
+        let (log_level, subscriber) = handle();
+
+        tracing::subscriber::with_default(subscriber, || {
+            log_level.set_for("debug", Duration::from_secs(60)).unwrap();
+            log_level
+                .set_for("trace", Duration::from_millis(50))
+                .unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            assert_eq!(log_level.directives(), "info");
+        });
+    }
+
+    #[test]
+    fn test_later_change_cancels_revert() {
+        let (log_level, subscriber) = handle();
+
+        tracing::subscriber::with_default(subscriber, || {
+            log_level
+                .set_for("trace", Duration::from_millis(50))
+                .unwrap();
+            log_level.set("warn").unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            assert_eq!(log_level.directives(), "warn");
+        });
+    }
+
+    #[test]
+    fn test_set_for_rejects_out_of_range_duration() {
+        let (log_level, subscriber) = handle();
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(matches!(
+                log_level.set_for("trace", Duration::from_secs(u64::MAX)),
+                Err(LogLevelError::Delay(_))
+            ));
+            assert_eq!(log_level.directives(), "info");
+            assert_eq!(log_level.revert_in(), None);
+            assert!(!tracing::enabled!(tracing::Level::DEBUG));
+        });
+    }
+
+    #[test]
+    fn test_reload_fails_without_subscriber() {
+        let (log_level, subscriber) = handle();
+        drop(subscriber);
+
+        assert!(matches!(
+            log_level.set("debug"),
+            Err(LogLevelError::Reload(_))
+        ));
+    }
+}
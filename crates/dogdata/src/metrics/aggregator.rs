@@ -0,0 +1,196 @@
+//! Client-side aggregation of the metrics recorded between two flushes.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Histogram and distribution samples written on a single line, so that a line always
+/// fits in a datagram.
+const MAX_SAMPLES_PER_LINE: usize = 32;
+
+/// A value recorded by the [`MetricsClient`](super::MetricsClient).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Sample<'a> {
+    Count(i64),
+    Gauge(f64),
+    Histogram(f64),
+    Distribution(f64),
+    Set(&'a str),
+}
+
+impl Sample<'_> {
+    fn metric_type(&self) -> &'static str {
+        match self {
+            Sample::Count(_) => "c",
+            Sample::Gauge(_) => "g",
+            Sample::Histogram(_) => "h",
+            Sample::Distribution(_) => "d",
+            Sample::Set(_) => "s",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MetricKey {
+    name: String,
+    metric_type: &'static str,
+    tags: String,
+}
+
+#[derive(Debug)]
+enum Value {
+    Count(i64),
+    Gauge(f64),
+    Samples(Vec<f64>),
+    Set(BTreeSet<String>),
+}
+
+/// Counts are summed, gauges keep the last value and sets their distinct values.
+/// Histogram and distribution samples are kept as is, the agent computes the percentiles.
+#[derive(Debug, Default)]
+pub(super) struct Aggregator {
+    metrics: HashMap<MetricKey, Value>,
+    buffered_samples: usize,
+}
+
+impl Aggregator {
+    pub(super) fn record(&mut self, name: &str, sample: Sample<'_>, tags: &[&str]) {
+        let key = MetricKey {
+            name: name.to_string(),
+            metric_type: sample.metric_type(),
+            tags: tags.join(","),
+        };
+        let value = self.metrics.entry(key).or_insert_with(|| match sample {
+            Sample::Count(_) => Value::Count(0),
+            Sample::Gauge(_) => Value::Gauge(0.0),
+            Sample::Histogram(_) | Sample::Distribution(_) => Value::Samples(Vec::new()),
+            Sample::Set(_) => Value::Set(BTreeSet::new()),
+        });
+
+        match (value, sample) {
+            (Value::Count(total), Sample::Count(it)) => *total = total.saturating_add(it),
+            (Value::Gauge(last), Sample::Gauge(it)) => *last = it,
+            (Value::Samples(samples), Sample::Histogram(it) | Sample::Distribution(it)) => {
+                samples.push(it);
+                self.buffered_samples += 1;
+            }
+            (Value::Set(values), Sample::Set(it)) => {
+                values.insert(it.to_string());
+            }
+            // the metric type is part of the key
+            _ => unreachable!(),
+        }
+    }
+
+    /// Number of histogram and distribution samples waiting for the next flush.
+    pub(super) fn buffered_samples(&self) -> usize {
+        self.buffered_samples
+    }
+
+    /// Empties the aggregator, returning one DogStatsD line per metric, with the constant
+    /// tags appended to the tags of each.
+    pub(super) fn drain(&mut self, constant_tags: &str) -> Vec<String> {
+        self.buffered_samples = 0;
+
+        let mut lines = Vec::with_capacity(self.metrics.len());
+        for (key, value) in self.metrics.drain() {
+            let tags = match (key.tags.is_empty(), constant_tags.is_empty()) {
+                (true, true) => String::new(),
+                (false, true) => format!("|#{}", key.tags),
+                (true, false) => format!("|#{constant_tags}"),
+                (false, false) => format!("|#{},{constant_tags}", key.tags),
+            };
+            let mut line = |values: &str| {
+                lines.push(format!("{}:{values}|{}{tags}", key.name, key.metric_type))
+            };
+
+            match value {
+                Value::Count(total) => line(&total.to_string()),
+                Value::Gauge(last) => line(&last.to_string()),
+                Value::Samples(samples) => {
+                    for chunk in samples.chunks(MAX_SAMPLES_PER_LINE) {
+                        let mut values = String::new();
+                        for sample in chunk {
+                            if !values.is_empty() {
+                                values.push(':');
+                            }
+                            let _ = write!(values, "{sample}");
+                        }
+                        line(&values);
+                    }
+                }
+                Value::Set(values) => values.iter().for_each(|it| line(it)),
+            }
+        }
+        lines
+    }
+}
+
+/// Packs lines into newline separated payloads of at most `max_size` bytes. A line longer
+/// than that is sent on its own.
+pub(super) fn pack(lines: &[String], max_size: usize) -> Vec<String> {
+    let mut payloads = Vec::new();
+    let mut payload = String::new();
+    for line in lines {
+        if !payload.is_empty() && payload.len() + 1 + line.len() > max_size {
+            payloads.push(std::mem::take(&mut payload));
+        }
+        if !payload.is_empty() {
+            payload.push('\n');
+        }
+        payload.push_str(line);
+    }
+    if !payload.is_empty() {
+        payloads.push(payload);
+    }
+    payloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregation() {
+        let mut aggregator = Aggregator::default();
+        aggregator.record("requests", Sample::Count(1), &["route:/a"]);
+        aggregator.record("requests", Sample::Count(2), &["route:/a"]);
+        aggregator.record("requests", Sample::Count(5), &["route:/b"]);
+        aggregator.record("queue.size", Sample::Gauge(3.0), &[]);
+        aggregator.record("queue.size", Sample::Gauge(1.5), &[]);
+        aggregator.record("latency", Sample::Histogram(10.0), &[]);
+        aggregator.record("latency", Sample::Histogram(12.5), &[]);
+        aggregator.record("payload", Sample::Distribution(512.0), &[]);
+        aggregator.record("users", Sample::Set("alice"), &[]);
+        aggregator.record("users", Sample::Set("alice"), &[]);
+        assert_eq!(aggregator.buffered_samples(), 3);
+
+        let mut lines = aggregator.drain("env:prod");
+        lines.sort();
+
+        assert_eq!(
+            lines,
+            [
+                "latency:10:12.5|h|#env:prod",
+                "payload:512|d|#env:prod",
+                "queue.size:1.5|g|#env:prod",
+                "requests:3|c|#route:/a,env:prod",
+                "requests:5|c|#route:/b,env:prod",
+                "users:alice|s|#env:prod",
+            ]
+        );
+        assert_eq!(aggregator.buffered_samples(), 0);
+        assert!(aggregator.drain("").is_empty());
+    }
+
+    #[test]
+    fn test_pack_splits_at_max_size() {
+        let lines: Vec<String> = ["a:1|c", "b:1|c", "c:1|c"]
+            .iter()
+            .map(|it| it.to_string())
+            .collect();
+
+        assert_eq!(pack(&lines, 1432), ["a:1|c\nb:1|c\nc:1|c"]);
+        assert_eq!(pack(&lines, 10), ["a:1|c", "b:1|c", "c:1|c"]);
+        assert_eq!(pack(&lines, 11), ["a:1|c\nb:1|c", "c:1|c"]);
+    }
+}
@@ -0,0 +1,318 @@
+//! Custom metrics sent to the DogStatsD server of the Datadog agent.
+//!
+//! [`init`](crate::init) installs a global [`MetricsClient`] when dogdata is enabled,
+//! tagged with the `service`, `env` and `version` of the configuration, and sending to
+//! `DD_DOGSTATSD_URL`. The free functions of this module record to it, and do nothing
+//! until it is installed:
+//!
+//! ```no_run
+//! dogdata::metrics::incr("invoices.created", &["plan:pro"]);
+//! dogdata::metrics::histogram("invoices.amount", 42.5, &["currency:eur"]);
+//! ```
+//!
+//! Metrics are aggregated in memory and sent every few seconds: counts are summed, gauges
+//! keep their last value and sets their distinct values, while histogram and distribution
+//! samples are sent as recorded. Sending is best effort, payloads that cannot be sent right
+//! away are dropped.
+
+mod aggregator;
+mod transport;
+
+use std::fmt::{self, Debug};
+use std::io;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+use aggregator::{Aggregator, Sample};
+use transport::Transport;
+
+pub use transport::StatsdEndpoint;
+
+use crate::config::DogdataConfig;
+
+/// Interval at which the aggregated metrics are sent.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+/// Histogram and distribution samples buffered before flushing early.
+const MAX_BUFFERED_SAMPLES: usize = 4096;
+
+static GLOBAL_CLIENT: RwLock<Option<MetricsClient>> = RwLock::new(None);
+
+struct Inner {
+    transport: Transport,
+    constant_tags: String,
+    aggregator: Mutex<Aggregator>,
+}
+
+impl Inner {
+    fn record(&self, name: &str, sample: Sample<'_>, tags: &[&str]) {
+        let mut aggregator = self
+            .aggregator
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        aggregator.record(name, sample, tags);
+        if aggregator.buffered_samples() >= MAX_BUFFERED_SAMPLES {
+            let lines = aggregator.drain(&self.constant_tags);
+            drop(aggregator);
+            self.send(&lines);
+        }
+    }
+
+    fn flush(&self) {
+        let lines = self
+            .aggregator
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .drain(&self.constant_tags);
+        self.send(&lines);
+    }
+
+    fn send(&self, lines: &[String]) {
+        for payload in aggregator::pack(lines, self.transport.max_payload()) {
+            let _ = self.transport.send(payload.as_bytes());
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Aggregates metrics and sends them to DogStatsD in the background.
+///
+/// Cloning the client is cheap, and all clones share the same buffer. Pending metrics are
+/// sent when the last clone is dropped, or on [`flush`](Self::flush).
+#[derive(Clone)]
+pub struct MetricsClient {
+    inner: Arc<Inner>,
+}
+
+impl MetricsClient {
+    /// Creates a client sending to `endpoint`, adding `constant_tags` to every metric.
+    pub fn new<T: Into<String>>(
+        endpoint: &StatsdEndpoint,
+        constant_tags: impl IntoIterator<Item = T>,
+    ) -> io::Result<Self> {
+        let constant_tags: Vec<String> = constant_tags.into_iter().map(Into::into).collect();
+        let inner = Arc::new(Inner {
+            transport: Transport::new(endpoint)?,
+            constant_tags: constant_tags.join(","),
+            aggregator: Mutex::new(Aggregator::default()),
+        });
+
+        // the flusher only holds a weak reference, so that it stops with the client
+        let weak: Weak<Inner> = Arc::downgrade(&inner);
+        thread::Builder::new()
+            .name("dogdata-metrics".to_string())
+            .spawn(move || {
+                loop {
+                    thread::sleep(FLUSH_INTERVAL);
+                    match weak.upgrade() {
+                        Some(inner) => inner.flush(),
+                        None => break,
+                    }
+                }
+            })?;
+
+        Ok(Self { inner })
+    }
+
+    /// Creates a client sending to the configured DogStatsD endpoint, tagged with the
+    /// `service`, `env` and `version` of the configuration.
+    pub fn from_config(config: &DogdataConfig) -> io::Result<Self> {
+        let endpoint: StatsdEndpoint = config
+            .dogstatsd_endpoint()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let tags = [
+            ("service", config.service()),
+            ("env", config.env()),
+            ("version", config.version()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| format!("{key}:{value}")));
+
+        Self::new(&endpoint, tags)
+    }
+
+    /// Adds `value` to a counter.
+    pub fn count(&self, name: &str, value: i64, tags: &[&str]) {
+        self.inner.record(name, Sample::Count(value), tags);
+    }
+
+    /// Adds one to a counter.
+    pub fn incr(&self, name: &str, tags: &[&str]) {
+        self.count(name, 1, tags);
+    }
+
+    /// Sets the value of a gauge.
+    pub fn gauge(&self, name: &str, value: f64, tags: &[&str]) {
+        self.inner.record(name, Sample::Gauge(value), tags);
+    }
+
+    /// Records a histogram sample, aggregated by the agent.
+    pub fn histogram(&self, name: &str, value: f64, tags: &[&str]) {
+        self.inner.record(name, Sample::Histogram(value), tags);
+    }
+
+    /// Records a distribution sample, aggregated across hosts by Datadog.
+    pub fn distribution(&self, name: &str, value: f64, tags: &[&str]) {
+        self.inner.record(name, Sample::Distribution(value), tags);
+    }
+
+    /// Adds a value to a set, counting the distinct values.
+    pub fn set(&self, name: &str, value: &str, tags: &[&str]) {
+        self.inner.record(name, Sample::Set(value), tags);
+    }
+
+    /// Sends the pending metrics now.
+    pub fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+impl Debug for MetricsClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsClient")
+            .field("transport", &self.inner.transport)
+            .field("constant_tags", &self.inner.constant_tags)
+            .finish()
+    }
+}
+
+/// Installs the client used by the free functions of this module, replacing the previous
+/// one.
+pub fn set_global_client(client: MetricsClient) {
+    let previous = GLOBAL_CLIENT
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .replace(client);
+    if let Some(previous) = previous {
+        previous.flush();
+    }
+}
+
+/// Returns the global client, if one is installed.
+pub fn global_client() -> Option<MetricsClient> {
+    GLOBAL_CLIENT
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+fn with_global_client(f: impl FnOnce(&MetricsClient)) {
+    if let Some(client) = GLOBAL_CLIENT
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+    {
+        f(client);
+    }
+}
+
+/// Adds `value` to a counter of the global client.
+pub fn count(name: &str, value: i64, tags: &[&str]) {
+    with_global_client(|client| client.count(name, value, tags));
+}
+
+/// Adds one to a counter of the global client.
+pub fn incr(name: &str, tags: &[&str]) {
+    with_global_client(|client| client.incr(name, tags));
+}
+
+/// Sets the value of a gauge of the global client.
+pub fn gauge(name: &str, value: f64, tags: &[&str]) {
+    with_global_client(|client| client.gauge(name, value, tags));
+}
+
+/// Records a histogram sample with the global client.
+pub fn histogram(name: &str, value: f64, tags: &[&str]) {
+    with_global_client(|client| client.histogram(name, value, tags));
+}
+
+/// Records a distribution sample with the global client.
+pub fn distribution(name: &str, value: f64, tags: &[&str]) {
+    with_global_client(|client| client.distribution(name, value, tags));
+}
+
+/// Adds a value to a set of the global client.
+pub fn set(name: &str, value: &str, tags: &[&str]) {
+    with_global_client(|client| client.set(name, value, tags));
+}
+
+/// Sends the pending metrics of the global client now.
+pub fn flush() {
+    with_global_client(MetricsClient::flush);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    fn listener() -> (UdpSocket, StatsdEndpoint) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let endpoint = StatsdEndpoint::Udp(socket.local_addr().unwrap().to_string());
+        (socket, endpoint)
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<String> {
+        let mut buf = [0; 1500];
+        let n = socket.recv(&mut buf).unwrap();
+        let mut lines: Vec<String> = std::str::from_utf8(&buf[..n])
+            .unwrap()
+            .lines()
+            .map(|it| it.to_string())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_sends_aggregated_metrics_over_udp() {
+        let (socket, endpoint) = listener();
+        let client = MetricsClient::new(&endpoint, ["service:billing", "env:prod"]).unwrap();
+
+        client.incr("invoices.created", &["plan:pro"]);
+        client.count("invoices.created", 2, &["plan:pro"]);
+        client.gauge("queue.size", 7.0, &[]);
+        client.histogram("invoices.amount", 12.5, &[]);
+        client.distribution("invoices.amount.dist", 3.0, &[]);
+        client.set("customers", "acme", &[]);
+        client.flush();
+
+        assert_eq!(
+            receive(&socket),
+            [
+                "customers:acme|s|#service:billing,env:prod",
+                "invoices.amount.dist:3|d|#service:billing,env:prod",
+                "invoices.amount:12.5|h|#service:billing,env:prod",
+                "invoices.created:3|c|#plan:pro,service:billing,env:prod",
+                "queue.size:7|g|#service:billing,env:prod",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unified_tags_from_config() {
+        let (socket, endpoint) = listener();
+        let config = DogdataConfig::default()
+            .with_service("billing")
+            .with_version("1.2.3")
+            .with_dogstatsd_endpoint(endpoint.to_string());
+        let client = MetricsClient::from_config(&config).unwrap();
+
+        client.incr("invoices.created", &[]);
+        drop(client);
+
+        assert_eq!(
+            receive(&socket),
+            ["invoices.created:1|c|#service:billing,version:1.2.3"]
+        );
+    }
+}
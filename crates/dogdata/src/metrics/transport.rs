@@ -0,0 +1,171 @@
+//! Datagram transport to the DogStatsD server of the agent.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Largest UDP payload that fits in a single ethernet frame.
+const UDP_MAX_PAYLOAD: usize = 1432;
+/// The agent reads Unix socket datagrams of up to 8KB.
+#[cfg(unix)]
+const UNIX_MAX_PAYLOAD: usize = 8192;
+
+/// Where the DogStatsD server is listening.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatsdEndpoint {
+    /// `udp://host:port`
+    Udp(String),
+    /// `unix:///path/to/dsd.socket`
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for StatsdEndpoint {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = url.strip_prefix("udp://") {
+            return match address.trim_end_matches('/') {
+                "" => Err(format!("missing address in dogstatsd url `{url}`")),
+                address => Ok(StatsdEndpoint::Udp(address.to_string())),
+            };
+        }
+
+        if let Some(path) = url.strip_prefix("unix://") {
+            #[cfg(unix)]
+            return match path {
+                "" => Err(format!("missing socket path in dogstatsd url `{url}`")),
+                path => Ok(StatsdEndpoint::Unix(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!(
+                "unix sockets are not supported on this platform: `{path}`"
+            ));
+        }
+
+        Err(format!(
+            "unsupported dogstatsd url `{url}`, expected a udp:// or unix:// scheme"
+        ))
+    }
+}
+
+impl fmt::Display for StatsdEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatsdEndpoint::Udp(address) => write!(f, "udp://{address}"),
+            #[cfg(unix)]
+            StatsdEndpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// A non-blocking datagram socket. Metrics are best effort: payloads that cannot be sent
+/// right away are dropped rather than stalling the caller.
+#[derive(Debug)]
+pub(super) enum Transport {
+    Udp {
+        socket: UdpSocket,
+        address: String,
+    },
+    #[cfg(unix)]
+    Unix {
+        socket: UnixDatagram,
+        path: PathBuf,
+    },
+}
+
+impl Transport {
+    pub(super) fn new(endpoint: &StatsdEndpoint) -> io::Result<Self> {
+        match endpoint {
+            StatsdEndpoint::Udp(address) => {
+                // the address is resolved again on every send, so the agent may move or come
+                // up later; only the address family has to be known upfront
+                let ipv6 = address
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut it| it.next())
+                    .is_some_and(|it| it.is_ipv6());
+                let local: SocketAddr = if ipv6 {
+                    ([0u16; 8], 0).into()
+                } else {
+                    ([0u8; 4], 0).into()
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.set_nonblocking(true)?;
+                Ok(Transport::Udp {
+                    socket,
+                    address: address.clone(),
+                })
+            }
+            #[cfg(unix)]
+            StatsdEndpoint::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.set_nonblocking(true)?;
+                Ok(Transport::Unix {
+                    socket,
+                    path: path.clone(),
+                })
+            }
+        }
+    }
+
+    pub(super) fn max_payload(&self) -> usize {
+        match self {
+            Transport::Udp { .. } => UDP_MAX_PAYLOAD,
+            #[cfg(unix)]
+            Transport::Unix { .. } => UNIX_MAX_PAYLOAD,
+        }
+    }
+
+    pub(super) fn send(&self, payload: &[u8]) -> io::Result<()> {
+        match self {
+            Transport::Udp { socket, address } => socket.send_to(payload, address.as_str())?,
+            #[cfg(unix)]
+            Transport::Unix { socket, path } => socket.send_to(payload, path)?,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_udp_endpoint() {
+        let endpoint: StatsdEndpoint = "udp://agent:8125".parse().unwrap();
+
+        assert_eq!(endpoint, StatsdEndpoint::Udp("agent:8125".to_string()));
+        assert_eq!(endpoint.to_string(), "udp://agent:8125");
+    }
+
+    #[test]
+    fn test_parse_invalid_endpoint() {
+        assert!("http://agent:8125".parse::<StatsdEndpoint>().is_err());
+        assert!("udp://".parse::<StatsdEndpoint>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_transport_sends_datagram() {
+        let path = std::env::temp_dir().join(format!("dogdata-{}-dsd.socket", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let endpoint: StatsdEndpoint = format!("unix://{}", path.display()).parse().unwrap();
+        let transport = Transport::new(&endpoint).unwrap();
+        transport.send(b"page.views:1|c").unwrap();
+
+        let mut buf = [0; 64];
+        let n = server.recv(&mut buf).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(&buf[..n], b"page.views:1|c");
+        assert_eq!(transport.max_payload(), UNIX_MAX_PAYLOAD);
+    }
+}
@@ -0,0 +1,277 @@
+//! Panic hook reporting panics to Datadog.
+//!
+//! The hook installed by [`install_hook`] marks the current span and its local root span as
+//! errored with `error.type=panic`, and logs the panic as a structured `ERROR` event,
+//! correlated with the trace by the [`DatadogFormatter`](crate::formatter::DatadogFormatter).
+//! It then calls the previous hook, which prints the panic to stderr by default.
+//!
+//! [`init`](crate::init) installs it when `DD_PANIC_HOOK_ENABLED=true`. With `panic = "abort"`
+//! the spans never end, so the hook exports the current span and its parents as they are,
+//! and flushes them with the pending spans and metrics before the process goes away. The
+//! flush runs on its own thread and is given up after [`ABORT_FLUSH_TIMEOUT`], as it needs the
+//! async runtime, which the panicking thread may be driving.
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::panic::{self, PanicHookInfo};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use opentelemetry::KeyValue;
+use opentelemetry::trace::{Span as _, Status, TracerProvider as _};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::{Level, dispatcher};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::Registry;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::error::{ERROR_MESSAGE, ERROR_STACK, ERROR_TYPE};
+use crate::shutdown::TracerShutdown;
+
+/// How long the hook waits for spans and metrics to be flushed when panics abort.
+pub const ABORT_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Installs the panic hook, chained with the current one.
+///
+/// `shutdown` holds the tracer provider flushed when panics abort.
+pub fn install_hook(shutdown: &TracerShutdown) {
+    let provider = shutdown.provider().cloned();
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        report(info);
+        if cfg!(panic = "abort") {
+            if let Some(provider) = &provider {
+                export_current_spans(provider);
+            }
+            flush(provider.clone(), ABORT_FLUSH_TIMEOUT);
+        }
+        previous(info);
+    }));
+}
+
+fn report(info: &PanicHookInfo<'_>) {
+    let message = payload_message(info);
+    let location = info.location().map(|it| it.to_string()).unwrap_or_default();
+    let stack = panic_stack(message, &location, &Backtrace::capture());
+
+    mark_current_spans(message, &stack);
+    tracing::event!(
+        target: "panic",
+        Level::ERROR,
+        error.type = "panic",
+        error.message = message,
+        error.stack = %stack,
+        location = %location,
+        "panicked at {location}: {message}"
+    );
+}
+
+fn payload_message<'a>(info: &'a PanicHookInfo<'_>) -> &'a str {
+    if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Formats the panic the way the default hook prints it.
+fn panic_stack(message: &str, location: &str, backtrace: &Backtrace) -> String {
+    let mut stack = format!("panicked at {location}:\n{message}");
+    if backtrace.status() == BacktraceStatus::Captured {
+        stack.push_str(&format!("\n\nStack backtrace:\n{backtrace}"));
+    }
+    stack
+}
+
+/// Sets the error status and tags on the current span and its local root. Does nothing
+/// outside of a span, or when the subscriber is not built on a [`Registry`].
+fn mark_current_spans(message: &str, stack: &str) {
+    dispatcher::get_default(|dispatch| {
+        let Some(id) = dispatch.current_span().id().cloned() else {
+            return;
+        };
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        let Some(span) = registry.span(&id) else {
+            return;
+        };
+
+        let root = span.scope().from_root().next().filter(|it| it.id() != id);
+        for span in std::iter::once(span).chain(root) {
+            if let Some(data) = span.extensions_mut().get_mut::<OtelData>() {
+                data.builder.status = Status::error(message.to_string());
+                let attributes = data.builder.attributes.get_or_insert_with(Vec::new);
+                // replaces an error recorded earlier on the span
+                attributes.retain(|kv| {
+                    !matches!(kv.key.as_str(), ERROR_TYPE | ERROR_MESSAGE | ERROR_STACK)
+                });
+                attributes.extend([
+                    KeyValue::new(ERROR_TYPE, "panic"),
+                    KeyValue::new(ERROR_MESSAGE, message.to_string()),
+                    KeyValue::new(ERROR_STACK, stack.to_string()),
+                ]);
+            }
+        }
+    });
+}
+
+/// Exports the current span and its parents, which never end when panics abort.
+fn export_current_spans(provider: &SdkTracerProvider) {
+    let tracer = provider.tracer("dogdata");
+    dispatcher::get_default(|dispatch| {
+        let Some(id) = dispatch.current_span().id().cloned() else {
+            return;
+        };
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        let Some(span) = registry.span(&id) else {
+            return;
+        };
+
+        for span in span.scope() {
+            if let Some(data) = span.extensions().get::<OtelData>() {
+                data.builder
+                    .clone()
+                    .start_with_context(&tracer, &data.parent_cx)
+                    .end();
+            }
+        }
+    });
+}
+
+/// Flushes the spans and metrics on another thread, waiting for it at most `timeout`.
+fn flush(provider: Option<SdkTracerProvider>, timeout: Duration) {
+    let (done, flushed) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name("dogdata-panic-flush".to_string())
+        .spawn(move || {
+            if let Some(provider) = provider {
+                let _ = provider.force_flush();
+            }
+            crate::metrics::flush();
+            let _ = done.send(());
+        });
+    if spawned.is_ok() {
+        let _ = flushed.recv_timeout(timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::{CapturingExporter, attribute};
+    use opentelemetry::Value;
+    use opentelemetry_sdk::runtime;
+    use opentelemetry_sdk::trace::span_processor_with_async_runtime::BatchSpanProcessor;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_mark_current_spans_marks_current_and_root() {
+        let exporter = CapturingExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            let parent = tracing::info_span!("parent");
+            let _parent = parent.enter();
+            let child = tracing::info_span!("child", error.type = "std::io::error::Error");
+            let _child = child.enter();
+
+            mark_current_spans("boom", "panicked at src/lib.rs:1:1:\nboom");
+        });
+
+        let spans = exporter.0.lock().unwrap();
+        let span = |name: &str| spans.iter().find(|it| it.name == name).unwrap();
+        for name in ["root", "child"] {
+            assert_eq!(span(name).status, Status::error("boom"));
+            assert_eq!(
+                attribute(span(name), ERROR_TYPE),
+                Some(&Value::from("panic"))
+            );
+            assert_eq!(
+                attribute(span(name), ERROR_STACK),
+                Some(&Value::from("panicked at src/lib.rs:1:1:\nboom"))
+            );
+        }
+        let error_types = span("child")
+            .attributes
+            .iter()
+            .filter(|it| it.key.as_str() == ERROR_TYPE)
+            .count();
+        assert_eq!(error_types, 1);
+        assert_eq!(span("parent").status, Status::Unset);
+        assert_eq!(attribute(span("parent"), ERROR_TYPE), None);
+    }
+
+    #[test]
+    fn test_export_current_spans_exports_open_scope() {
+        let exporter = CapturingExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            let child = tracing::info_span!("child");
+            let _child = child.enter();
+
+            mark_current_spans("boom", "panicked at src/lib.rs:1:1:\nboom");
+            export_current_spans(&provider);
+
+            // the spans are still open
+            let spans = exporter.0.lock().unwrap();
+            assert_eq!(spans.len(), 2);
+            let root = spans.iter().find(|it| it.name == "root").unwrap();
+            let child = spans.iter().find(|it| it.name == "child").unwrap();
+            assert_eq!(child.parent_span_id, root.span_context.span_id());
+            assert_eq!(child.span_context.trace_id(), root.span_context.trace_id());
+            assert_eq!(child.status, Status::error("boom"));
+            assert_eq!(attribute(child, ERROR_TYPE), Some(&Value::from("panic")));
+        });
+    }
+
+    #[test]
+    fn test_flush_gives_up_after_timeout() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // the batch processor needs the runtime, which is not driven by any thread
+        let provider = runtime.block_on(async {
+            SdkTracerProvider::builder()
+                .with_span_processor(
+                    BatchSpanProcessor::builder(CapturingExporter::default(), runtime::Tokio)
+                        .build(),
+                )
+                .build()
+        });
+
+        let start = std::time::Instant::now();
+        flush(Some(provider), Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_panic_stack() {
+        assert_eq!(
+            panic_stack("boom", "src/main.rs:3:5", &Backtrace::disabled()),
+            "panicked at src/main.rs:3:5:\nboom"
+        );
+        assert!(
+            panic_stack("boom", "src/main.rs:3:5", &Backtrace::force_capture())
+                .starts_with("panicked at src/main.rs:3:5:\nboom\n\nStack backtrace:\n")
+        );
+    }
+}
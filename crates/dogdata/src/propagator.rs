@@ -0,0 +1,860 @@
+// Copyright The OpenTelemetry Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Datadog header propagation. Adapted from [opentelemetry-datadog v0.16.0](https://github.com/open-telemetry/opentelemetry-rust-contrib/blob/opentelemetry-datadog-0.16.0/opentelemetry-datadog/src/lib.rs)
+//!
+//! Unlike upstream, the full range of sampling priorities is carried through: the
+//! `x-datadog-sampling-priority` header is stored in the trace state, where the
+//! [sampler](crate::sampler) and the [manual overrides](crate::trace) read and update it.
+//!
+//! The upper 64 bits of 128-bit trace ids travel in the `_dd.p.tid` tag of the
+//! `x-datadog-tags` header, as the other Datadog tracers do. The other `_dd.p.*`
+//! propagation tags of the header, such as the `_dd.p.dm` decision maker, are kept in the
+//! trace state as `t.*` members, the way Datadog writes them to the W3C `tracestate`
+//! header, and injected again at the next hop.
+//!
+//! [`CompositePropagator`] combines the Datadog headers with W3C trace context, B3 and
+//! baggage, as selected by `DD_TRACE_PROPAGATION_STYLE_EXTRACT` and
+//! `DD_TRACE_PROPAGATION_STYLE_INJECT`. It is installed as the global propagator by
+//! [`init`](crate::init) and [`build_tracer_provider`](crate::tracer::build_tracer_provider),
+//! so it is used by the axum layer and the reqwest middleware alike.
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use opentelemetry::Context;
+use opentelemetry::propagation::text_map_propagator::FieldIter;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+
+use crate::sampler::{AUTO_KEEP, AUTO_REJECT, TRACE_STATE_KEY, USER_KEEP, USER_REJECT};
+use crate::sampler::{sampling_priority, with_sampling_priority};
+
+const DATADOG_TRACE_ID_HEADER: &str = "x-datadog-trace-id";
+const DATADOG_PARENT_ID_HEADER: &str = "x-datadog-parent-id";
+const DATADOG_SAMPLING_PRIORITY_HEADER: &str = "x-datadog-sampling-priority";
+const DATADOG_TAGS_HEADER: &str = "x-datadog-tags";
+
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+const B3_SINGLE_HEADER: &str = "b3";
+
+/// Propagation tag holding the upper 64 bits of the trace id, as 16 hex characters.
+pub(crate) const TRACE_ID_HIGH_TAG: &str = "_dd.p.tid";
+
+/// Prefix of the propagation tags of the `x-datadog-tags` header.
+const PROPAGATION_TAG_PREFIX: &str = "_dd.p.";
+/// Prefix of the propagation tags in the `dd` member of the trace state.
+const TRACE_STATE_TAG_PREFIX: &str = "t.";
+
+/// Marks contexts extracted without a sampling priority, leaving the decision to this service.
+pub(crate) const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
+
+static TRACE_CONTEXT_HEADER_FIELDS: OnceLock<[String; 4]> = OnceLock::new();
+
+fn trace_context_header_fields() -> &'static [String; 4] {
+    TRACE_CONTEXT_HEADER_FIELDS.get_or_init(|| {
+        [
+            DATADOG_TRACE_ID_HEADER.to_owned(),
+            DATADOG_PARENT_ID_HEADER.to_owned(),
+            DATADOG_SAMPLING_PRIORITY_HEADER.to_owned(),
+            DATADOG_TAGS_HEADER.to_owned(),
+        ]
+    })
+}
+
+/// The upper 64 bits of a 128-bit trace id, if any.
+pub(crate) fn trace_id_high(trace_id: TraceId) -> Option<u64> {
+    let high = (u128::from_be_bytes(trace_id.to_bytes()) >> 64) as u64;
+    (high != 0).then_some(high)
+}
+
+/// Returns a copy of the trace state holding the `_dd.p.*` tags of an `x-datadog-tags`
+/// header, except `_dd.p.tid`, which is part of the trace id. Tags that cannot be written
+/// to the trace state are dropped.
+fn with_propagation_tags(trace_state: &TraceState, tags: &str) -> TraceState {
+    let tags: Vec<String> = tags
+        .split(',')
+        .filter_map(|tag| tag.trim().split_once('='))
+        .filter(|(key, _)| *key != TRACE_ID_HIGH_TAG)
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(PROPAGATION_TAG_PREFIX)?;
+            let valid = |it: &str, reserved: &[u8]| {
+                !it.is_empty()
+                    && it
+                        .bytes()
+                        .all(|b| b.is_ascii_graphic() && !reserved.contains(&b))
+            };
+            // `=` is not allowed in the trace state, so Datadog writes it as `~`
+            (valid(name, b",;:=~") && valid(value, b",;~"))
+                .then(|| format!("{TRACE_STATE_TAG_PREFIX}{name}:{}", value.replace('=', "~")))
+        })
+        .collect();
+    if tags.is_empty() {
+        return trace_state.clone();
+    }
+
+    let mut members: Vec<String> = trace_state
+        .get(TRACE_STATE_KEY)
+        .into_iter()
+        .flat_map(|value| value.split(';'))
+        .filter(|member| !member.is_empty() && !member.starts_with(TRACE_STATE_TAG_PREFIX))
+        .map(str::to_string)
+        .collect();
+    members.extend(tags);
+    trace_state
+        .insert(TRACE_STATE_KEY, members.join(";"))
+        .unwrap_or_else(|_| trace_state.clone())
+}
+
+/// The `_dd.p.*` tags kept in the trace state, as written to the `x-datadog-tags` header.
+fn propagation_tags(trace_state: &TraceState) -> impl Iterator<Item = String> + '_ {
+    trace_state
+        .get(TRACE_STATE_KEY)
+        .into_iter()
+        .flat_map(|value| value.split(';'))
+        .filter_map(|member| member.strip_prefix(TRACE_STATE_TAG_PREFIX)?.split_once(':'))
+        .filter(|(name, _)| TRACE_ID_HIGH_TAG.strip_prefix(PROPAGATION_TAG_PREFIX) != Some(name))
+        .map(|(name, value)| format!("{PROPAGATION_TAG_PREFIX}{name}={}", value.replace('~', "=")))
+}
+
+static B3_MULTI_HEADER_FIELDS: OnceLock<[String; 4]> = OnceLock::new();
+static B3_SINGLE_HEADER_FIELDS: OnceLock<[String; 1]> = OnceLock::new();
+
+fn b3_multi_header_fields() -> &'static [String; 4] {
+    B3_MULTI_HEADER_FIELDS.get_or_init(|| {
+        [
+            B3_TRACE_ID_HEADER.to_owned(),
+            B3_SPAN_ID_HEADER.to_owned(),
+            B3_SAMPLED_HEADER.to_owned(),
+            B3_FLAGS_HEADER.to_owned(),
+        ]
+    })
+}
+
+fn b3_single_header_fields() -> &'static [String; 1] {
+    B3_SINGLE_HEADER_FIELDS.get_or_init(|| [B3_SINGLE_HEADER.to_owned()])
+}
+
+#[derive(Debug)]
+enum ExtractError {
+    TraceId,
+    SpanId,
+    SamplingPriority,
+}
+
+/// Extracts and injects `SpanContext`s into `Extractor`s or `Injector`s using Datadog's header format.
+///
+/// ```
+/// use opentelemetry::global;
+/// use dogdata::propagator::DatadogPropagator;
+///
+/// global::set_text_map_propagator(DatadogPropagator::default());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DatadogPropagator {
+    _private: (),
+}
+
+impl DatadogPropagator {
+    /// Creates a new `DatadogPropagator`.
+    pub fn new() -> Self {
+        DatadogPropagator::default()
+    }
+
+    fn extract_trace_id(&self, trace_id: &str, tags: &str) -> Result<TraceId, ExtractError> {
+        let low = trace_id.parse::<u64>().map_err(|_| ExtractError::TraceId)?;
+        // a malformed `_dd.p.tid` only loses the upper bits, as in the other tracers
+        let high = tags
+            .split(',')
+            .filter_map(|tag| tag.trim().split_once('='))
+            .find(|(key, _)| *key == TRACE_ID_HIGH_TAG)
+            .filter(|(_, value)| value.len() == 16)
+            .and_then(|(_, value)| u64::from_str_radix(value, 16).ok())
+            .unwrap_or_default();
+
+        Ok(TraceId::from(((high as u128) << 64) | low as u128))
+    }
+
+    fn extract_span_id(&self, span_id: &str) -> Result<SpanId, ExtractError> {
+        span_id
+            .parse::<u64>()
+            .map(SpanId::from)
+            .map_err(|_| ExtractError::SpanId)
+    }
+
+    fn extract_sampling_priority(&self, sampling_priority: &str) -> Result<i8, ExtractError> {
+        match sampling_priority.parse::<i8>() {
+            Ok(priority @ USER_REJECT..=USER_KEEP) => Ok(priority),
+            _ => Err(ExtractError::SamplingPriority),
+        }
+    }
+
+    fn extract_span_context(&self, extractor: &dyn Extractor) -> Result<SpanContext, ExtractError> {
+        let tags = extractor.get(DATADOG_TAGS_HEADER).unwrap_or("");
+        let trace_id =
+            self.extract_trace_id(extractor.get(DATADOG_TRACE_ID_HEADER).unwrap_or(""), tags)?;
+        // If we have a trace_id but can't get the parent span, we default it to invalid instead of completely erroring
+        // out so that the rest of the spans aren't completely lost
+        let span_id = self
+            .extract_span_id(extractor.get(DATADOG_PARENT_ID_HEADER).unwrap_or(""))
+            .unwrap_or(SpanId::INVALID);
+        let sampling_priority = self.extract_sampling_priority(
+            extractor
+                .get(DATADOG_SAMPLING_PRIORITY_HEADER)
+                .unwrap_or(""),
+        );
+
+        let (trace_flags, trace_state) = match sampling_priority {
+            Ok(priority) => (
+                if priority >= AUTO_KEEP {
+                    TraceFlags::SAMPLED
+                } else {
+                    TraceFlags::default()
+                },
+                with_sampling_priority(&TraceState::NONE, priority),
+            ),
+            // Treat the sampling as DEFERRED instead of erroring on extracting the span context
+            Err(_) => (TRACE_FLAG_DEFERRED, TraceState::NONE),
+        };
+
+        Ok(SpanContext::new(
+            trace_id,
+            span_id,
+            trace_flags,
+            true,
+            with_propagation_tags(&trace_state, tags),
+        ))
+    }
+}
+
+fn get_sampling_priority(span_context: &SpanContext) -> Option<i8> {
+    sampling_priority(span_context.trace_state()).or_else(|| {
+        if span_context.trace_flags() & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED {
+            None
+        } else if span_context.is_sampled() {
+            Some(AUTO_KEEP)
+        } else {
+            Some(AUTO_REJECT)
+        }
+    })
+}
+
+impl TextMapPropagator for DatadogPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            injector.set(
+                DATADOG_TRACE_ID_HEADER,
+                (u128::from_be_bytes(span_context.trace_id().to_bytes()) as u64).to_string(),
+            );
+            injector.set(
+                DATADOG_PARENT_ID_HEADER,
+                u64::from_be_bytes(span_context.span_id().to_bytes()).to_string(),
+            );
+            let tags: Vec<String> = trace_id_high(span_context.trace_id())
+                .map(|high| format!("{TRACE_ID_HIGH_TAG}={high:016x}"))
+                .into_iter()
+                .chain(propagation_tags(span_context.trace_state()))
+                .collect();
+            if !tags.is_empty() {
+                injector.set(DATADOG_TAGS_HEADER, tags.join(","));
+            }
+
+            if let Some(sampling_priority) = get_sampling_priority(span_context) {
+                injector.set(
+                    DATADOG_SAMPLING_PRIORITY_HEADER,
+                    sampling_priority.to_string(),
+                );
+            }
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        self.extract_span_context(extractor)
+            .map(|sc| cx.with_remote_span_context(sc))
+            .unwrap_or_else(|_| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(trace_context_header_fields())
+    }
+}
+
+/// Extracts and injects `SpanContext`s using the [B3](https://github.com/openzipkin/b3-propagation)
+/// headers, either the `x-b3-*` headers or the single `b3` header.
+///
+/// As with [`DatadogPropagator`], a context without a sampling decision is marked deferred so
+/// that the decision is made by this service.
+#[derive(Clone, Debug)]
+pub struct B3Propagator {
+    single_header: bool,
+}
+
+impl B3Propagator {
+    /// Creates a propagator for the `x-b3-traceid`, `x-b3-spanid` and `x-b3-sampled` headers.
+    pub fn multi_header() -> Self {
+        Self {
+            single_header: false,
+        }
+    }
+
+    /// Creates a propagator for the single `b3` header.
+    pub fn single_header() -> Self {
+        Self {
+            single_header: true,
+        }
+    }
+
+    fn extract_trace_id(&self, trace_id: &str) -> Result<TraceId, ExtractError> {
+        if !matches!(trace_id.len(), 16 | 32) || !trace_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ExtractError::TraceId);
+        }
+        TraceId::from_hex(trace_id).map_err(|_| ExtractError::TraceId)
+    }
+
+    fn extract_span_id(&self, span_id: &str) -> Result<SpanId, ExtractError> {
+        if span_id.len() != 16 || !span_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ExtractError::SpanId);
+        }
+        SpanId::from_hex(span_id).map_err(|_| ExtractError::SpanId)
+    }
+
+    fn extract_trace_flags(&self, sampled: Option<&str>) -> Result<TraceFlags, ExtractError> {
+        match sampled {
+            Some("1" | "true" | "d") => Ok(TraceFlags::SAMPLED),
+            Some("0" | "false") => Ok(TraceFlags::default()),
+            None => Ok(TRACE_FLAG_DEFERRED),
+            Some(_) => Err(ExtractError::SamplingPriority),
+        }
+    }
+
+    fn extract_span_context(&self, extractor: &dyn Extractor) -> Result<SpanContext, ExtractError> {
+        let (trace_id, span_id, sampled) = if self.single_header {
+            let mut parts = extractor.get(B3_SINGLE_HEADER).unwrap_or("").split('-');
+            (
+                parts.next().unwrap_or(""),
+                parts.next().unwrap_or(""),
+                parts.next(),
+            )
+        } else {
+            let sampled = match extractor.get(B3_FLAGS_HEADER) {
+                Some("1") => Some("d"),
+                _ => extractor.get(B3_SAMPLED_HEADER),
+            };
+            (
+                extractor.get(B3_TRACE_ID_HEADER).unwrap_or(""),
+                extractor.get(B3_SPAN_ID_HEADER).unwrap_or(""),
+                sampled,
+            )
+        };
+
+        Ok(SpanContext::new(
+            self.extract_trace_id(trace_id)?,
+            self.extract_span_id(span_id)?,
+            self.extract_trace_flags(sampled)?,
+            true,
+            TraceState::NONE,
+        ))
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+
+        let trace_id = match trace_id_high(span_context.trace_id()) {
+            Some(_) => format!("{}", span_context.trace_id()),
+            None => format!(
+                "{:016x}",
+                u128::from_be_bytes(span_context.trace_id().to_bytes()) as u64
+            ),
+        };
+        let span_id = format!("{}", span_context.span_id());
+        let sampled = get_sampling_priority(span_context)
+            .map(|priority| if priority >= AUTO_KEEP { "1" } else { "0" });
+
+        if self.single_header {
+            let value = match sampled {
+                Some(sampled) => format!("{trace_id}-{span_id}-{sampled}"),
+                None => format!("{trace_id}-{span_id}"),
+            };
+            injector.set(B3_SINGLE_HEADER, value);
+        } else {
+            injector.set(B3_TRACE_ID_HEADER, trace_id);
+            injector.set(B3_SPAN_ID_HEADER, span_id);
+            if let Some(sampled) = sampled {
+                injector.set(B3_SAMPLED_HEADER, sampled.to_owned());
+            }
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        self.extract_span_context(extractor)
+            .map(|sc| cx.with_remote_span_context(sc))
+            .unwrap_or_else(|_| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        if self.single_header {
+            FieldIter::new(b3_single_header_fields())
+        } else {
+            FieldIter::new(b3_multi_header_fields())
+        }
+    }
+}
+
+/// A header format, as named in `DD_TRACE_PROPAGATION_STYLE_EXTRACT` and
+/// `DD_TRACE_PROPAGATION_STYLE_INJECT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropagationStyle {
+    /// The `x-datadog-*` headers, see [`DatadogPropagator`].
+    Datadog,
+    /// The W3C `traceparent` and `tracestate` headers.
+    TraceContext,
+    /// The `x-b3-*` headers.
+    B3Multi,
+    /// The single `b3` header.
+    B3,
+    /// The W3C `baggage` header.
+    Baggage,
+}
+
+impl PropagationStyle {
+    /// The styles used when none are configured, as in the other Datadog tracers.
+    pub const DEFAULT: &[PropagationStyle] = &[
+        PropagationStyle::Datadog,
+        PropagationStyle::TraceContext,
+        PropagationStyle::Baggage,
+    ];
+
+    /// Parses a comma separated list of styles, skipping unknown ones. `none` selects no
+    /// style at all.
+    pub(crate) fn parse_list(styles: &str) -> Vec<Self> {
+        styles
+            .split(',')
+            .filter_map(|style| style.parse().ok())
+            .collect()
+    }
+
+    fn propagator(self) -> Box<dyn TextMapPropagator + Send + Sync> {
+        match self {
+            PropagationStyle::Datadog => Box::new(DatadogPropagator::new()),
+            PropagationStyle::TraceContext => Box::new(TraceContextPropagator::new()),
+            PropagationStyle::B3Multi => Box::new(B3Propagator::multi_header()),
+            PropagationStyle::B3 => Box::new(B3Propagator::single_header()),
+            PropagationStyle::Baggage => Box::new(BaggagePropagator::new()),
+        }
+    }
+}
+
+impl FromStr for PropagationStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "datadog" => Ok(PropagationStyle::Datadog),
+            "tracecontext" => Ok(PropagationStyle::TraceContext),
+            "b3multi" => Ok(PropagationStyle::B3Multi),
+            "b3" | "b3 single header" => Ok(PropagationStyle::B3),
+            "baggage" => Ok(PropagationStyle::Baggage),
+            other => Err(format!("unknown propagation style `{other}`")),
+        }
+    }
+}
+
+/// Combines several [`PropagationStyle`]s.
+///
+/// Extraction stops at the first style that yields a valid span context, in the configured
+/// order, as in the other Datadog tracers. Baggage is always extracted. Injection writes the
+/// headers of every style.
+///
+/// ```
+/// use opentelemetry::global;
+/// use dogdata::propagator::{CompositePropagator, PropagationStyle};
+///
+/// global::set_text_map_propagator(CompositePropagator::new(
+///     &[PropagationStyle::Datadog, PropagationStyle::B3Multi],
+///     &[PropagationStyle::Datadog],
+/// ));
+/// ```
+#[derive(Debug)]
+pub struct CompositePropagator {
+    extract: Vec<(PropagationStyle, Box<dyn TextMapPropagator + Send + Sync>)>,
+    inject: Vec<Box<dyn TextMapPropagator + Send + Sync>>,
+    fields: Vec<String>,
+}
+
+impl CompositePropagator {
+    /// Creates a propagator extracting and injecting the given styles.
+    pub fn new(extract: &[PropagationStyle], inject: &[PropagationStyle]) -> Self {
+        let extract: Vec<_> = extract
+            .iter()
+            .map(|style| (*style, style.propagator()))
+            .collect();
+        let inject: Vec<_> = inject.iter().map(|style| style.propagator()).collect();
+
+        let mut fields = Vec::new();
+        for propagator in extract.iter().map(|(_, it)| it).chain(&inject) {
+            for field in propagator.fields() {
+                if !fields.iter().any(|it| it == field) {
+                    fields.push(field.to_owned());
+                }
+            }
+        }
+
+        Self {
+            extract,
+            inject,
+            fields,
+        }
+    }
+}
+
+impl Default for CompositePropagator {
+    fn default() -> Self {
+        Self::new(PropagationStyle::DEFAULT, PropagationStyle::DEFAULT)
+    }
+}
+
+impl TextMapPropagator for CompositePropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        for propagator in &self.inject {
+            propagator.inject_context(cx, injector);
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        let mut cx = cx.clone();
+        let mut extracted = false;
+        for (style, propagator) in &self.extract {
+            if *style == PropagationStyle::Baggage {
+                cx = propagator.extract_with_context(&cx, extractor);
+                continue;
+            }
+            if extracted {
+                continue;
+            }
+
+            let span_context = propagator
+                .extract_with_context(&Context::new(), extractor)
+                .span()
+                .span_context()
+                .clone();
+            if span_context.is_valid() {
+                cx = cx.with_remote_span_context(span_context);
+                extracted = true;
+            }
+        }
+        cx
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn priority(priority: i8) -> TraceState {
+        with_sampling_priority(&TraceState::NONE, priority)
+    }
+
+    fn dd(value: &str) -> TraceState {
+        TraceState::from_key_value([(TRACE_STATE_KEY, value)]).unwrap()
+    }
+
+    #[rustfmt::skip]
+    fn extract_test_data() -> Vec<(Vec<(&'static str, &'static str)>, SpanContext)> {
+        vec![
+            (vec![], SpanContext::empty_context()),
+            (vec![(DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::empty_context()),
+            (vec![(DATADOG_TRACE_ID_HEADER, "garbage")], SpanContext::empty_context()),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "garbage")], SpanContext::new(TraceId::from(1234), SpanId::INVALID, TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "3")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "-1")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::default(), true, priority(USER_REJECT))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::default(), true, priority(AUTO_REJECT))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, priority(AUTO_KEEP))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, priority(USER_KEEP))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_TAGS_HEADER, "_dd.p.dm=-4,_dd.p.tid=640cfd8d00000000")], SpanContext::new(TraceId::from(0x640c_fd8d_0000_0000_0000_0000_0000_04d2), SpanId::from(12), TRACE_FLAG_DEFERRED, true, dd("t.dm:-4"))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2"), (DATADOG_TAGS_HEADER, "_dd.p.dm=-3,_dd.p.usr.id=dXNlcg==,_dd.p.bad=a;b,other=x")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, dd("s:2;t.dm:-3;t.usr.id:dXNlcg~~"))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_TAGS_HEADER, "_dd.p.tid=garbage")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+        ]
+    }
+
+    #[rustfmt::skip]
+    fn inject_test_data() -> Vec<(Vec<(&'static str, &'static str)>, SpanContext)> {
+        vec![
+            (vec![], SpanContext::empty_context()),
+            (vec![], SpanContext::new(TraceId::INVALID, SpanId::INVALID, TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (vec![], SpanContext::new(TraceId::from(1234), SpanId::INVALID, TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::default(), true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, priority(USER_KEEP))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "-1")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::default(), true, priority(USER_REJECT))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1"), (DATADOG_TAGS_HEADER, "_dd.p.tid=640cfd8d00000000")], SpanContext::new(TraceId::from(0x640c_fd8d_0000_0000_0000_0000_0000_04d2), SpanId::from(12), TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1"), (DATADOG_TAGS_HEADER, "_dd.p.dm=-4")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, dd("s:1;t.dm:-4"))),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2"), (DATADOG_TAGS_HEADER, "_dd.p.tid=640cfd8d00000000,_dd.p.dm=-3,_dd.p.usr.id=dXNlcg==")], SpanContext::new(TraceId::from(0x640c_fd8d_0000_0000_0000_0000_0000_04d2), SpanId::from(12), TraceFlags::SAMPLED, true, dd("s:2;t.dm:-3;t.tid:0000000000000000;t.usr.id:dXNlcg~~"))),
+        ]
+    }
+
+    #[test]
+    fn test_extract() {
+        for (header_list, expected) in extract_test_data() {
+            let map: HashMap<String, String> = header_list
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+
+            let propagator = DatadogPropagator::default();
+            let context = propagator.extract(&map);
+            assert_eq!(context.span().span_context(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_inject() {
+        let propagator = DatadogPropagator::default();
+        for (header_values, span_context) in inject_test_data() {
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::new().with_remote_span_context(span_context),
+                &mut injector,
+            );
+
+            let expected: HashMap<String, String> = header_values
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(injector, expected);
+        }
+    }
+
+    #[test]
+    fn test_propagation_tags_kept_across_hops() {
+        let propagator = DatadogPropagator::default();
+        let headers: HashMap<String, String> = [
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (
+                DATADOG_TAGS_HEADER,
+                "_dd.p.dm=-4,_dd.p.tid=640cfd8d00000000",
+            ),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let context = propagator.extract(&headers);
+        // the priority is updated by the sampler of this service
+        let span_context = context.span().span_context().clone();
+        let span_context = SpanContext::new(
+            span_context.trace_id(),
+            SpanId::from(13),
+            span_context.trace_flags(),
+            false,
+            with_sampling_priority(span_context.trace_state(), USER_KEEP),
+        );
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(
+            &Context::new().with_remote_span_context(span_context),
+            &mut injector,
+        );
+
+        assert_eq!(
+            injector[DATADOG_TAGS_HEADER],
+            "_dd.p.tid=640cfd8d00000000,_dd.p.dm=-4"
+        );
+        assert_eq!(injector[DATADOG_SAMPLING_PRIORITY_HEADER], "2");
+    }
+
+    type Headers = Vec<(&'static str, &'static str)>;
+
+    #[rustfmt::skip]
+    fn b3_extract_test_data() -> Vec<(B3Propagator, Headers, SpanContext)> {
+        let multi = B3Propagator::multi_header;
+        let single = B3Propagator::single_header;
+        vec![
+            (multi(), vec![], SpanContext::empty_context()),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "garbage"), (B3_SPAN_ID_HEADER, "000000000000000c")], SpanContext::empty_context()),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "00000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "640cfd8d0000000000000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c"), (B3_SAMPLED_HEADER, "1")], SpanContext::new(TraceId::from(0x640c_fd8d_0000_0000_0000_0000_0000_04d2), SpanId::from(12), TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "00000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c"), (B3_SAMPLED_HEADER, "0")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::default(), true, TraceState::NONE)),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "00000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c"), (B3_SAMPLED_HEADER, "0"), (B3_FLAGS_HEADER, "1")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (single(), vec![(B3_SINGLE_HEADER, "0")], SpanContext::empty_context()),
+            (single(), vec![(B3_SINGLE_HEADER, "00000000000004d2-000000000000000c")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (single(), vec![(B3_SINGLE_HEADER, "00000000000004d2-000000000000000c-d-0000000000000001")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (single(), vec![(B3_SINGLE_HEADER, "00000000000004d2-000000000000000c-x")], SpanContext::empty_context()),
+        ]
+    }
+
+    #[rustfmt::skip]
+    fn b3_inject_test_data() -> Vec<(B3Propagator, Headers, SpanContext)> {
+        let multi = B3Propagator::multi_header;
+        let single = B3Propagator::single_header;
+        vec![
+            (multi(), vec![], SpanContext::empty_context()),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "00000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "640cfd8d0000000000000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c"), (B3_SAMPLED_HEADER, "1")], SpanContext::new(TraceId::from(0x640c_fd8d_0000_0000_0000_0000_0000_04d2), SpanId::from(12), TraceFlags::SAMPLED, true, TraceState::NONE)),
+            (multi(), vec![(B3_TRACE_ID_HEADER, "00000000000004d2"), (B3_SPAN_ID_HEADER, "000000000000000c"), (B3_SAMPLED_HEADER, "0")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, priority(USER_REJECT))),
+            (single(), vec![(B3_SINGLE_HEADER, "00000000000004d2-000000000000000c")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TRACE_FLAG_DEFERRED, true, TraceState::NONE)),
+            (single(), vec![(B3_SINGLE_HEADER, "00000000000004d2-000000000000000c-1")], SpanContext::new(TraceId::from(1234), SpanId::from(12), TraceFlags::SAMPLED, true, priority(USER_KEEP))),
+        ]
+    }
+
+    #[test]
+    fn test_b3_extract() {
+        for (propagator, header_list, expected) in b3_extract_test_data() {
+            let map: HashMap<String, String> = header_list
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+
+            let context = propagator.extract(&map);
+            assert_eq!(context.span().span_context(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_b3_inject() {
+        for (propagator, header_values, span_context) in b3_inject_test_data() {
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::new().with_remote_span_context(span_context),
+                &mut injector,
+            );
+
+            let expected: HashMap<String, String> = header_values
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(injector, expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_propagation_styles() {
+        assert_eq!(
+            PropagationStyle::parse_list("Datadog, tracecontext,b3multi,b3 single header,garbage"),
+            vec![
+                PropagationStyle::Datadog,
+                PropagationStyle::TraceContext,
+                PropagationStyle::B3Multi,
+                PropagationStyle::B3,
+            ]
+        );
+        assert!(PropagationStyle::parse_list("none").is_empty());
+    }
+
+    #[test]
+    fn test_composite_extract_stops_at_first_valid_context() {
+        let propagator = CompositePropagator::new(
+            &[
+                PropagationStyle::Datadog,
+                PropagationStyle::TraceContext,
+                PropagationStyle::Baggage,
+            ],
+            &[],
+        );
+        let headers: HashMap<String, String> = [
+            ("x-datadog-trace-id", "garbage"),
+            (
+                "traceparent",
+                "00-000000000000000000000000000004d2-000000000000000c-01",
+            ),
+            ("x-b3-traceid", "00000000000010e1"),
+            ("x-b3-spanid", "000000000000000d"),
+            ("baggage", "tenant=acme"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let context = propagator.extract(&headers);
+
+        let span_context = context.span().span_context().clone();
+        assert_eq!(span_context.trace_id(), TraceId::from(1234));
+        assert_eq!(span_context.span_id(), SpanId::from(12));
+        assert!(span_context.is_sampled());
+        assert_eq!(
+            opentelemetry::baggage::BaggageExt::baggage(&context)
+                .get("tenant")
+                .map(|it| it.as_str().to_string()),
+            Some("acme".to_string())
+        );
+
+        // the first style wins, even though b3 is also valid
+        let propagator =
+            CompositePropagator::new(&[PropagationStyle::B3Multi, PropagationStyle::Datadog], &[]);
+        let context = propagator.extract(&headers);
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from(4321)
+        );
+    }
+
+    #[test]
+    fn test_composite_inject_writes_every_style() {
+        let propagator = CompositePropagator::new(
+            &[],
+            &[PropagationStyle::Datadog, PropagationStyle::TraceContext],
+        );
+        let span_context = SpanContext::new(
+            TraceId::from(1234),
+            SpanId::from(12),
+            TraceFlags::SAMPLED,
+            true,
+            priority(USER_KEEP),
+        );
+
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(
+            &Context::new().with_remote_span_context(span_context),
+            &mut injector,
+        );
+
+        assert_eq!(injector[DATADOG_TRACE_ID_HEADER], "1234");
+        assert_eq!(injector[DATADOG_SAMPLING_PRIORITY_HEADER], "2");
+        assert_eq!(
+            injector["traceparent"],
+            "00-000000000000000000000000000004d2-000000000000000c-01"
+        );
+        assert_eq!(injector["tracestate"], "dd=s:2");
+        assert!(propagator.fields().any(|field| field == "traceparent"));
+    }
+
+    #[test]
+    fn test_composite_without_styles_is_noop() {
+        let propagator = CompositePropagator::new(&[], &[]);
+        let headers: HashMap<String, String> =
+            [("x-datadog-trace-id".to_string(), "1234".to_string())].into();
+
+        assert!(!propagator.extract(&headers).has_active_span());
+        assert_eq!(propagator.fields().count(), 0);
+    }
+}
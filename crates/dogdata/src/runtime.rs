@@ -0,0 +1,174 @@
+//! Tokio runtime metrics, reported as `runtime.rust.*` through DogStatsD.
+//!
+//! Sampling happens on a dedicated thread rather than a task, so that a starved runtime
+//! cannot delay its own metrics. [`init`](crate::init) starts a reporter for the current
+//! runtime when `DD_RUNTIME_METRICS_ENABLED=true`, stopped by
+//! [`TracerShutdown::shutdown`](crate::shutdown::TracerShutdown::shutdown).
+//!
+//! | metric | type | |
+//! |--------|------|-|
+//! | `runtime.rust.tokio.workers` | gauge | worker threads |
+//! | `runtime.rust.tokio.alive_tasks` | gauge | tasks spawned and not yet completed |
+//! | `runtime.rust.tokio.global_queue_depth` | gauge | tasks waiting in the injection queue |
+//! | `runtime.rust.tokio.busy_duration` | count | milliseconds spent polling tasks, summed over the workers |
+//! | `runtime.rust.tokio.busy_ratio` | gauge | share of the worker time spent polling tasks |
+//! | `runtime.rust.tokio.blocking_threads` | gauge | threads of the blocking pool, with `--cfg tokio_unstable` |
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Handle;
+
+use crate::metrics::MetricsClient;
+
+/// Default interval between two samples.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+const WORKERS: &str = "runtime.rust.tokio.workers";
+const ALIVE_TASKS: &str = "runtime.rust.tokio.alive_tasks";
+const GLOBAL_QUEUE_DEPTH: &str = "runtime.rust.tokio.global_queue_depth";
+#[cfg(target_has_atomic = "64")]
+const BUSY_DURATION: &str = "runtime.rust.tokio.busy_duration";
+#[cfg(target_has_atomic = "64")]
+const BUSY_RATIO: &str = "runtime.rust.tokio.busy_ratio";
+#[cfg(tokio_unstable)]
+const BLOCKING_THREADS: &str = "runtime.rust.tokio.blocking_threads";
+
+/// Reads the metrics of a runtime, keeping the cumulative ones to report their increase.
+struct Sampler {
+    handle: Handle,
+    last_sample: Instant,
+    last_busy: Duration,
+}
+
+impl Sampler {
+    fn new(handle: Handle) -> Self {
+        let mut sampler = Self {
+            handle,
+            last_sample: Instant::now(),
+            last_busy: Duration::ZERO,
+        };
+        sampler.last_busy = sampler.total_busy_duration();
+        sampler
+    }
+
+    fn total_busy_duration(&self) -> Duration {
+        #[cfg(target_has_atomic = "64")]
+        {
+            let metrics = self.handle.metrics();
+            (0..metrics.num_workers())
+                .map(|worker| metrics.worker_total_busy_duration(worker))
+                .sum()
+        }
+        #[cfg(not(target_has_atomic = "64"))]
+        Duration::ZERO
+    }
+
+    fn sample(&mut self, client: &MetricsClient) {
+        let metrics = self.handle.metrics();
+        let workers = metrics.num_workers();
+
+        client.gauge(WORKERS, workers as f64, &[]);
+        client.gauge(ALIVE_TASKS, metrics.num_alive_tasks() as f64, &[]);
+        client.gauge(GLOBAL_QUEUE_DEPTH, metrics.global_queue_depth() as f64, &[]);
+        #[cfg(tokio_unstable)]
+        client.gauge(BLOCKING_THREADS, metrics.num_blocking_threads() as f64, &[]);
+
+        let now = Instant::now();
+        let busy = self.total_busy_duration();
+        #[cfg(target_has_atomic = "64")]
+        {
+            let busy_delta = busy.saturating_sub(self.last_busy);
+            let available = now.duration_since(self.last_sample).as_secs_f64() * workers as f64;
+            client.count(BUSY_DURATION, busy_delta.as_millis() as i64, &[]);
+            if available > 0.0 {
+                let ratio = (busy_delta.as_secs_f64() / available).min(1.0);
+                client.gauge(BUSY_RATIO, ratio, &[]);
+            }
+        }
+        self.last_sample = now;
+        self.last_busy = busy;
+    }
+}
+
+/// Samples the metrics of a tokio runtime on an interval, until stopped or dropped.
+///
+/// The metrics are tagged with the constant tags of the client, which are the unified
+/// service tags for a client created with
+/// [`MetricsClient::from_config`](crate::metrics::MetricsClient::from_config).
+#[derive(Debug)]
+pub struct RuntimeMetricsReporter {
+    stop: Sender<()>,
+}
+
+impl RuntimeMetricsReporter {
+    /// Starts sampling the runtime of `handle` every `interval`.
+    pub fn start(handle: Handle, client: MetricsClient, interval: Duration) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel();
+        let mut sampler = Sampler::new(handle);
+
+        thread::Builder::new()
+            .name("dogdata-runtime-metrics".to_string())
+            .spawn(move || {
+                // the channel disconnects when the reporter is dropped
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    sampler.sample(&client);
+                }
+            })?;
+
+        Ok(Self { stop })
+    }
+
+    /// Stops sampling.
+    pub fn stop(&self) {
+        let _ = self.stop.send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::StatsdEndpoint;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_sample_reports_runtime_metrics() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let endpoint = StatsdEndpoint::Udp(socket.local_addr().unwrap().to_string());
+        let client = MetricsClient::new(&endpoint, ["service:billing"]).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+        let mut sampler = Sampler::new(runtime.handle().clone());
+        runtime.block_on(async {
+            tokio::spawn(async {}).await.unwrap();
+        });
+        sampler.sample(&client);
+        client.flush();
+
+        let mut buf = [0; 1500];
+        let n = socket.recv(&mut buf).unwrap();
+        let payload = std::str::from_utf8(&buf[..n]).unwrap();
+        let lines: Vec<_> = payload.lines().collect();
+
+        assert!(lines.contains(&"runtime.rust.tokio.workers:2|g|#service:billing"));
+        assert!(lines.contains(&"runtime.rust.tokio.global_queue_depth:0|g|#service:billing"));
+        assert!(
+            lines
+                .iter()
+                .any(|it| it.starts_with("runtime.rust.tokio.alive_tasks:"))
+        );
+        assert!(
+            lines
+                .iter()
+                .any(|it| it.starts_with("runtime.rust.tokio.busy_ratio:"))
+        );
+    }
+}
@@ -0,0 +1,778 @@
+//! Datadog-style trace sampling.
+//!
+//! The decision is made once, on the local root span, and inherited by every span below it
+//! as well as by downstream services through propagation:
+//!
+//! 1. The first [`SamplingRule`] from `DD_TRACE_SAMPLING_RULES` matching the root span's
+//!    service, name and resource decides the rate. `DD_TRACE_SAMPLE_RATE` acts as a catch-all
+//!    rule after them. Traces kept by a rule then go through the `DD_TRACE_RATE_LIMIT` limiter.
+//!    Those decisions are `USER_KEEP` (2) or `USER_REJECT` (-1).
+//! 2. Without a matching rule, the rate the agent returned for the service and env in its
+//!    `rate_by_service` response is used, with `AUTO_KEEP` (1) or `AUTO_REJECT` (0). Until
+//!    the agent has answered, every trace is kept.
+//!
+//! The rates that were applied are recorded on the root span as `_dd.rule_psr`,
+//! `_dd.limit_psr` or `_dd.agent_psr`, and the priority as `_sampling_priority_v1`, so that the backend can
+//! extrapolate metrics from the kept traces.
+//!
+//! The priority travels in the span's [`TraceState`], under the `dd` key, in the same
+//! `s:<priority>` format that Datadog uses in the W3C `tracestate` header.
+//!
+//! Rejected traces are recorded but not sampled, so that other exporters skip them. They
+//! are still sent to the agent with their priority, unless the stats are computed
+//! client-side, as the agent needs the whole traffic for its stats and rates.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use opentelemetry::trace::{
+    Link, SamplingDecision, SamplingResult, SpanContext, SpanKind, TraceContextExt, TraceId,
+    TraceState,
+};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{ShouldSample, Span, SpanData, SpanProcessor};
+use serde::Deserialize;
+
+use crate::config::DogdataConfig;
+use crate::propagator::TRACE_FLAG_DEFERRED;
+
+/// Metric holding the sampling priority of the trace.
+pub(crate) const SAMPLING_PRIORITY_KEY: &str = "_sampling_priority_v1";
+/// Metric holding the rate of the sampling rule that matched the trace.
+pub(crate) const RULE_RATE_KEY: &str = "_dd.rule_psr";
+/// Metric holding the effective rate of the rate limiter.
+pub(crate) const LIMIT_RATE_KEY: &str = "_dd.limit_psr";
+/// Metric holding the rate the agent asked for.
+pub(crate) const AGENT_RATE_KEY: &str = "_dd.agent_psr";
+
+pub(crate) const USER_REJECT: i8 = -1;
+pub(crate) const AUTO_REJECT: i8 = 0;
+pub(crate) const AUTO_KEEP: i8 = 1;
+pub(crate) const USER_KEEP: i8 = 2;
+
+/// Attributes that override the span name when matching the `name` and `resource` of a rule.
+const OPERATION_NAME_ATTRIBUTE: &str = "operation.name";
+const RESOURCE_NAME_ATTRIBUTE: &str = "resource.name";
+
+pub(crate) const TRACE_STATE_KEY: &str = "dd";
+const TRACE_STATE_PRIORITY: &str = "s";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.72.1/ddtrace/tracer/sampler.go#L93
+const KNUTH_FACTOR: u64 = 1_111_111_111_111_111_111;
+
+/// Reads the sampling priority stored in the trace state.
+pub(crate) fn sampling_priority(trace_state: &TraceState) -> Option<i8> {
+    trace_state
+        .get(TRACE_STATE_KEY)?
+        .split(';')
+        .find_map(|member| member.strip_prefix("s:"))
+        .and_then(|priority| priority.parse().ok())
+}
+
+/// Returns a copy of the trace state with the sampling priority replaced.
+pub(crate) fn with_sampling_priority(trace_state: &TraceState, priority: i8) -> TraceState {
+    let mut members = vec![format!("{TRACE_STATE_PRIORITY}:{priority}")];
+    if let Some(value) = trace_state.get(TRACE_STATE_KEY) {
+        members.extend(
+            value
+                .split(';')
+                .filter(|member| !member.is_empty() && !member.starts_with("s:"))
+                .map(str::to_string),
+        );
+    }
+    trace_state
+        .insert(TRACE_STATE_KEY, members.join(";"))
+        .unwrap_or_else(|_| trace_state.clone())
+}
+
+/// A rule of `DD_TRACE_SAMPLING_RULES`.
+///
+/// The `service`, `name` and `resource` patterns are case-insensitive globs, where `*`
+/// matches any sequence of characters and `?` a single one. A missing pattern matches
+/// anything.
+///
+/// `name` is compared with the `operation.name` attribute of the span and `resource` with
+/// the `resource.name` attribute, both falling back to the span name.
+///
+/// ```
+/// use dogdata::sampler::SamplingRule;
+///
+/// let rules: Vec<SamplingRule> = serde_json::from_str(
+///     r#"[{"service": "billing-*", "resource": "GET /health", "sample_rate": 0.0}]"#,
+/// )
+/// .unwrap();
+/// assert_eq!(
+///     rules[0],
+///     SamplingRule::new(0.0).with_service("billing-*").with_resource("GET /health")
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SamplingRule {
+    #[serde(default)]
+    service: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    resource: Option<String>,
+    #[serde(default = "default_rule_sample_rate")]
+    sample_rate: f64,
+}
+
+fn default_rule_sample_rate() -> f64 {
+    1.0
+}
+
+impl SamplingRule {
+    /// A rule matching every trace, keeping them at `sample_rate`.
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            service: None,
+            name: None,
+            resource: None,
+            sample_rate,
+        }
+    }
+
+    pub fn with_service<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.service = Some(pattern.into());
+        self
+    }
+
+    pub fn with_name<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.name = Some(pattern.into());
+        self
+    }
+
+    pub fn with_resource<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.resource = Some(pattern.into());
+        self
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn matches(&self, service: &str, name: &str, resource: &str) -> bool {
+        let matches = |pattern: &Option<String>, value: &str| {
+            pattern
+                .as_deref()
+                .is_none_or(|pattern| glob_match(pattern, value))
+        };
+        matches(&self.service, service)
+            && matches(&self.name, name)
+            && matches(&self.resource, resource)
+    }
+}
+
+/// Case-insensitive glob matching supporting `*` and `?`.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let value: Vec<char> = value.chars().flat_map(char::to_lowercase).collect();
+
+    let (mut p, mut v) = (0, 0);
+    // position of the last `*` in the pattern, and of the value when it was reached
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Deterministic sampling on the lower 64 bits of the trace id, so that every tracer
+/// makes the same decision for a given trace and rate.
+fn sampled_by_rate(trace_id: TraceId, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    let id = u128::from_be_bytes(trace_id.to_bytes()) as u64;
+    id.wrapping_mul(KNUTH_FACTOR) < (rate * u64::MAX as f64) as u64
+}
+
+/// Token bucket allowing `limit` traces per second, which also tracks the effective rate
+/// over the current and previous one-second windows.
+#[derive(Debug)]
+struct RateLimiter {
+    limit: f64,
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    window_allowed: u64,
+    window_total: u64,
+    previous_rate: Option<f64>,
+}
+
+impl RateLimiter {
+    fn new(limit: f64, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit,
+            last_refill: now,
+            window_start: now,
+            window_allowed: 0,
+            window_total: 0,
+            previous_rate: None,
+        }
+    }
+
+    fn is_allowed(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= Duration::from_secs(1) {
+            self.previous_rate = (elapsed < Duration::from_secs(2)).then(|| self.current_rate());
+            self.window_start = now;
+            self.window_allowed = 0;
+            self.window_total = 0;
+        }
+
+        let refill = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64()
+            * self.limit;
+        self.tokens = (self.tokens + refill).min(self.limit.max(1.0));
+        self.last_refill = now;
+
+        let allowed = self.limit > 0.0 && self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+            self.window_allowed += 1;
+        }
+        self.window_total += 1;
+        allowed
+    }
+
+    fn current_rate(&self) -> f64 {
+        match self.window_total {
+            0 => 1.0,
+            total => self.window_allowed as f64 / total as f64,
+        }
+    }
+
+    fn effective_rate(&self) -> f64 {
+        match self.previous_rate {
+            Some(previous) => (previous + self.current_rate()) / 2.0,
+            None => self.current_rate(),
+        }
+    }
+}
+
+/// Key of the rate used for services the agent has no specific rate for.
+const DEFAULT_AGENT_RATE_KEY: &str = "service:,env:";
+
+#[derive(Deserialize)]
+struct AgentResponse {
+    rate_by_service: HashMap<String, f64>,
+}
+
+/// Sample rates keyed by `service:<service>,env:<env>`, as returned by the agent in
+/// response to trace submissions. Shared between the exporter, which updates them, and the
+/// sampler.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AgentRates(Arc<RwLock<HashMap<String, f64>>>);
+
+impl AgentRates {
+    /// Replaces the rates with the ones of a `/v0.5/traces` response body. Bodies without
+    /// `rate_by_service` leave the rates untouched.
+    pub(crate) fn update(&self, body: &[u8]) {
+        if let Ok(response) = serde_json::from_slice::<AgentResponse>(body) {
+            *self.0.write().unwrap_or_else(|err| err.into_inner()) = response.rate_by_service;
+        }
+    }
+
+    pub(crate) fn rate(&self, key: &str) -> f64 {
+        let rates = self.0.read().unwrap_or_else(|err| err.into_inner());
+        rates
+            .get(key)
+            .or_else(|| rates.get(DEFAULT_AGENT_RATE_KEY))
+            .copied()
+            .unwrap_or(1.0)
+    }
+}
+
+/// [`ShouldSample`] implementing Datadog's sampling rules, rate limit and agent rates.
+#[derive(Debug, Clone)]
+pub(crate) struct DatadogSampler {
+    service: String,
+    agent_rate_key: String,
+    rules: Arc<[SamplingRule]>,
+    limiter: Arc<Mutex<RateLimiter>>,
+    agent_rates: AgentRates,
+}
+
+impl DatadogSampler {
+    pub(crate) fn new(config: &DogdataConfig, service: &str, agent_rates: AgentRates) -> Self {
+        let rules = config
+            .sampling_rules()
+            .iter()
+            .cloned()
+            .chain(config.sample_rate().map(SamplingRule::new))
+            .collect();
+
+        Self {
+            service: service.to_string(),
+            agent_rate_key: format!("service:{service},env:{}", config.env().unwrap_or_default()),
+            rules,
+            limiter: Arc::new(Mutex::new(RateLimiter::new(
+                config.rate_limit(),
+                Instant::now(),
+            ))),
+            agent_rates,
+        }
+    }
+
+    /// The decision for a trace starting here: its priority and the rates that led to it.
+    fn sample_root(
+        &self,
+        trace_id: TraceId,
+        name: &str,
+        attributes: &[KeyValue],
+    ) -> (i8, Vec<KeyValue>) {
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.as_str())
+        };
+        let operation_name = attribute(OPERATION_NAME_ATTRIBUTE);
+        let resource = attribute(RESOURCE_NAME_ATTRIBUTE);
+
+        let Some(rule) = self.rules.iter().find(|rule| {
+            rule.matches(
+                &self.service,
+                operation_name.as_deref().unwrap_or(name),
+                resource.as_deref().unwrap_or(name),
+            )
+        }) else {
+            let rate = self.agent_rates.rate(&self.agent_rate_key);
+            let priority = if sampled_by_rate(trace_id, rate) {
+                AUTO_KEEP
+            } else {
+                AUTO_REJECT
+            };
+            return (priority, vec![KeyValue::new(AGENT_RATE_KEY, rate)]);
+        };
+
+        let mut attributes = vec![KeyValue::new(RULE_RATE_KEY, rule.sample_rate)];
+        if !sampled_by_rate(trace_id, rule.sample_rate) {
+            return (USER_REJECT, attributes);
+        }
+
+        let mut limiter = self.limiter.lock().unwrap_or_else(|err| err.into_inner());
+        let allowed = limiter.is_allowed(Instant::now());
+        attributes.push(KeyValue::new(
+            LIMIT_RATE_KEY,
+            Value::F64(limiter.effective_rate()),
+        ));
+
+        (if allowed { USER_KEEP } else { USER_REJECT }, attributes)
+    }
+}
+
+fn decision(priority: i8) -> SamplingDecision {
+    if priority > AUTO_REJECT {
+        SamplingDecision::RecordAndSample
+    } else {
+        // rejected spans are still recorded so that the decision keeps propagating, and so
+        // that the `RejectedTraceProcessor` can send them to the agent
+        SamplingDecision::RecordOnly
+    }
+}
+
+impl ShouldSample for DatadogSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        if let Some(parent) = parent_context.filter(|cx| cx.has_active_span()) {
+            let span = parent.span();
+            let parent = span.span_context();
+            let deferred = parent.is_remote()
+                && parent.trace_flags() & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED;
+            if parent.is_valid() && !deferred {
+                let priority =
+                    sampling_priority(parent.trace_state()).unwrap_or(if parent.is_sampled() {
+                        AUTO_KEEP
+                    } else {
+                        AUTO_REJECT
+                    });
+                return SamplingResult {
+                    decision: decision(priority),
+                    attributes: Vec::new(),
+                    trace_state: with_sampling_priority(parent.trace_state(), priority),
+                };
+            }
+        }
+
+        let (priority, attributes) = self.sample_root(trace_id, name, attributes);
+        let trace_state = parent_context
+            .map(|cx| cx.span().span_context().trace_state().clone())
+            .unwrap_or_default();
+
+        SamplingResult {
+            decision: decision(priority),
+            attributes,
+            trace_state: with_sampling_priority(&trace_state, priority),
+        }
+    }
+}
+
+/// Hands the spans of rejected traces to the wrapped processor as if they were sampled.
+///
+/// Without client-side stats, the agent computes the hits, errors and latency of a service
+/// from every trace it receives, and the `rate_by_service` it answers with is based on the
+/// same traffic. The rejected traces are sent along, with their priority, so that both
+/// account for them; the agent drops them once its stats are computed.
+#[derive(Debug)]
+pub(crate) struct RejectedTraceProcessor<P> {
+    inner: P,
+}
+
+impl<P> RejectedTraceProcessor<P> {
+    pub(crate) fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for RejectedTraceProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let context = &span.span_context;
+        if !context.is_sampled() {
+            span.span_context = SpanContext::new(
+                context.trace_id(),
+                context.span_id(),
+                context.trace_flags().with_sampled(true),
+                context.is_remote(),
+                context.trace_state().clone(),
+            );
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::tests::CapturingExporter;
+    use opentelemetry::trace::{SpanId, TraceFlags, Tracer as _, TracerProvider as _};
+    use opentelemetry_sdk::trace::{SdkTracerProvider, SimpleSpanProcessor};
+
+    fn sampler(config: DogdataConfig) -> DatadogSampler {
+        DatadogSampler::new(&config, "billing", AgentRates::default())
+    }
+
+    fn sample(sampler: &DatadogSampler, name: &str, attributes: &[KeyValue]) -> SamplingResult {
+        sampler.should_sample(
+            None,
+            TraceId::from(42),
+            name,
+            &SpanKind::Server,
+            attributes,
+            &[],
+        )
+    }
+
+    fn attribute(result: &SamplingResult, key: &str) -> Option<Value> {
+        result
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("billing", "Billing"));
+        assert!(glob_match("bill*", "billing"));
+        assert!(glob_match("*-api", "billing-api"));
+        assert!(glob_match("b?lling", "billing"));
+        assert!(glob_match("*i*g*", "billing"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("bill", "billing"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("*-api", "billing-worker"));
+    }
+
+    #[test]
+    fn test_trace_state_priority() {
+        let trace_state =
+            TraceState::from_key_value([("dd", "s:1;o:rum"), ("other", "x")]).unwrap();
+
+        assert_eq!(sampling_priority(&trace_state), Some(AUTO_KEEP));
+        let trace_state = with_sampling_priority(&trace_state, USER_REJECT);
+        assert_eq!(trace_state.get("dd"), Some("s:-1;o:rum"));
+        assert_eq!(trace_state.get("other"), Some("x"));
+        assert_eq!(sampling_priority(&TraceState::NONE), None);
+    }
+
+    #[test]
+    fn test_keeps_without_rules() {
+        let result = sample(&sampler(DogdataConfig::default()), "request", &[]);
+
+        assert_eq!(result.decision, SamplingDecision::RecordAndSample);
+        assert_eq!(sampling_priority(&result.trace_state), Some(AUTO_KEEP));
+        assert_eq!(attribute(&result, AGENT_RATE_KEY), Some(Value::F64(1.0)));
+    }
+
+    #[test]
+    fn test_agent_rates() {
+        let agent_rates = AgentRates::default();
+        let sampler = DatadogSampler::new(
+            &DogdataConfig::default().with_env("prod"),
+            "billing",
+            agent_rates.clone(),
+        );
+
+        agent_rates.update(
+            br#"{"rate_by_service": {"service:billing,env:prod": 0.0, "service:,env:": 1.0}}"#,
+        );
+        let result = sample(&sampler, "request", &[]);
+
+        assert_eq!(result.decision, SamplingDecision::RecordOnly);
+        assert_eq!(sampling_priority(&result.trace_state), Some(AUTO_REJECT));
+        assert_eq!(attribute(&result, AGENT_RATE_KEY), Some(Value::F64(0.0)));
+        assert_eq!(attribute(&result, RULE_RATE_KEY), None);
+
+        agent_rates.update(br#"{"rate_by_service": {"service:,env:": 1.0}}"#);
+        agent_rates.update(b"OK");
+        let result = sample(&sampler, "request", &[]);
+
+        assert_eq!(sampling_priority(&result.trace_state), Some(AUTO_KEEP));
+        assert_eq!(attribute(&result, AGENT_RATE_KEY), Some(Value::F64(1.0)));
+    }
+
+    #[test]
+    fn test_global_sample_rate() {
+        let rejecting = sampler(DogdataConfig::default().with_sample_rate(0.0));
+        let result = sample(&rejecting, "request", &[]);
+
+        assert_eq!(result.decision, SamplingDecision::RecordOnly);
+        assert_eq!(sampling_priority(&result.trace_state), Some(USER_REJECT));
+        assert_eq!(attribute(&result, RULE_RATE_KEY), Some(Value::F64(0.0)));
+        assert_eq!(attribute(&result, LIMIT_RATE_KEY), None);
+
+        let keeping = sampler(DogdataConfig::default().with_sample_rate(1.0));
+        let result = sample(&keeping, "request", &[]);
+
+        assert_eq!(result.decision, SamplingDecision::RecordAndSample);
+        assert_eq!(sampling_priority(&result.trace_state), Some(USER_KEEP));
+        assert_eq!(attribute(&result, RULE_RATE_KEY), Some(Value::F64(1.0)));
+        assert_eq!(attribute(&result, LIMIT_RATE_KEY), Some(Value::F64(1.0)));
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let sampler = sampler(
+            DogdataConfig::default()
+                .with_sampling_rule(SamplingRule::new(0.0).with_resource("GET /health"))
+                .with_sampling_rule(SamplingRule::new(1.0).with_service("bill*"))
+                .with_sample_rate(0.0),
+        );
+
+        let health = sample(
+            &sampler,
+            "request",
+            &[KeyValue::new(RESOURCE_NAME_ATTRIBUTE, "GET /health")],
+        );
+        let other = sample(&sampler, "GET /users", &[]);
+
+        assert_eq!(sampling_priority(&health.trace_state), Some(USER_REJECT));
+        assert_eq!(sampling_priority(&other.trace_state), Some(USER_KEEP));
+        assert_eq!(attribute(&other, AGENT_RATE_KEY), None);
+    }
+
+    #[test]
+    fn test_rule_matches_operation_name() {
+        let sampler = sampler(
+            DogdataConfig::default()
+                .with_sampling_rule(SamplingRule::new(0.0).with_name("http.request")),
+        );
+
+        let matching = sample(
+            &sampler,
+            "GET /users",
+            &[KeyValue::new(OPERATION_NAME_ATTRIBUTE, "http.request")],
+        );
+        let other = sample(&sampler, "GET /users", &[]);
+
+        assert_eq!(sampling_priority(&matching.trace_state), Some(USER_REJECT));
+        assert_eq!(sampling_priority(&other.trace_state), Some(AUTO_KEEP));
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let sampler = sampler(
+            DogdataConfig::default()
+                .with_sample_rate(1.0)
+                .with_rate_limit(1.0),
+        );
+
+        let first = sample(&sampler, "request", &[]);
+        let second = sample(&sampler, "request", &[]);
+
+        assert_eq!(sampling_priority(&first.trace_state), Some(USER_KEEP));
+        assert_eq!(sampling_priority(&second.trace_state), Some(USER_REJECT));
+        assert_eq!(second.decision, SamplingDecision::RecordOnly);
+        assert_eq!(attribute(&second, LIMIT_RATE_KEY), Some(Value::F64(0.5)));
+    }
+
+    #[test]
+    fn test_rate_limiter_windows() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2.0, start);
+
+        assert!(limiter.is_allowed(start));
+        assert!(limiter.is_allowed(start));
+        assert!(!limiter.is_allowed(start));
+        assert!(!limiter.is_allowed(start));
+        assert_eq!(limiter.effective_rate(), 0.5);
+
+        let next = start + Duration::from_secs(1);
+        assert!(limiter.is_allowed(next));
+        assert_eq!(limiter.effective_rate(), 0.75);
+    }
+
+    #[test]
+    fn test_sampled_by_rate_is_deterministic() {
+        let kept = (0..1000u128)
+            .filter(|id| sampled_by_rate(TraceId::from(*id), 0.5))
+            .count();
+
+        assert!((400..600).contains(&kept), "kept {kept} of 1000");
+        assert!(!sampled_by_rate(TraceId::from(42), 0.0));
+        assert_eq!(
+            sampled_by_rate(TraceId::from(42), 0.5),
+            sampled_by_rate(TraceId::from(42), 0.5)
+        );
+    }
+
+    #[test]
+    fn test_inherits_parent_decision() {
+        let sampler = sampler(DogdataConfig::default().with_sample_rate(1.0));
+        let parent = SpanContext::new(
+            TraceId::from(42),
+            SpanId::from(1),
+            TraceFlags::default(),
+            true,
+            TraceState::NONE,
+        );
+        let cx = Context::new().with_remote_span_context(parent);
+
+        let result = sampler.should_sample(
+            Some(&cx),
+            TraceId::from(42),
+            "request",
+            &SpanKind::Server,
+            &[],
+            &[],
+        );
+
+        assert_eq!(result.decision, SamplingDecision::RecordOnly);
+        assert_eq!(sampling_priority(&result.trace_state), Some(AUTO_REJECT));
+        assert!(result.attributes.is_empty());
+    }
+
+    #[test]
+    fn test_decides_for_deferred_parent() {
+        let sampler = sampler(DogdataConfig::default().with_sample_rate(1.0));
+        let parent = SpanContext::new(
+            TraceId::from(42),
+            SpanId::from(1),
+            TRACE_FLAG_DEFERRED,
+            true,
+            TraceState::NONE,
+        );
+        let cx = Context::new().with_remote_span_context(parent);
+
+        let result = sampler.should_sample(
+            Some(&cx),
+            TraceId::from(42),
+            "request",
+            &SpanKind::Server,
+            &[],
+            &[],
+        );
+
+        assert_eq!(sampling_priority(&result.trace_state), Some(USER_KEEP));
+    }
+
+    #[test]
+    fn test_rejected_traces_sent_with_priority() {
+        let to_agent = CapturingExporter::default();
+        let others = CapturingExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_sampler(sampler(DogdataConfig::default().with_sample_rate(0.0)))
+            .with_span_processor(RejectedTraceProcessor::new(SimpleSpanProcessor::new(
+                Box::new(to_agent.clone()),
+            )))
+            .with_simple_exporter(others.clone())
+            .build();
+
+        provider.tracer("test").in_span("request", |_| {});
+
+        let spans = to_agent.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].span_context.is_sampled());
+        assert_eq!(
+            sampling_priority(spans[0].span_context.trace_state()),
+            Some(USER_REJECT)
+        );
+        assert!(others.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rules_from_json() {
+        let rules: Vec<SamplingRule> = serde_json::from_str(
+            r#"[{"service": "billing", "name": "http.*"}, {"sample_rate": 0.25}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules,
+            vec![
+                SamplingRule::new(1.0)
+                    .with_service("billing")
+                    .with_name("http.*"),
+                SamplingRule::new(0.25),
+            ]
+        );
+    }
+}
@@ -1,17 +1,46 @@
+use crate::appender::DroppedLinesReporter;
+use crate::runtime::RuntimeMetricsReporter;
 use opentelemetry_sdk::trace::SdkTracerProvider;
 
 pub struct TracerShutdown {
     provider: Option<SdkTracerProvider>,
+    runtime_metrics: Option<RuntimeMetricsReporter>,
+    dropped_lines: Option<DroppedLinesReporter>,
 }
 
 impl TracerShutdown {
     pub fn new(provider: Option<SdkTracerProvider>) -> Self {
-        Self { provider }
+        Self {
+            provider,
+            runtime_metrics: None,
+            dropped_lines: None,
+        }
+    }
+
+    pub(crate) fn with_runtime_metrics(mut self, reporter: Option<RuntimeMetricsReporter>) -> Self {
+        self.runtime_metrics = reporter;
+        self
+    }
+
+    pub(crate) fn with_dropped_lines(mut self, reporter: Option<DroppedLinesReporter>) -> Self {
+        self.dropped_lines = reporter;
+        self
+    }
+
+    pub(crate) fn provider(&self) -> Option<&SdkTracerProvider> {
+        self.provider.as_ref()
     }
 
     pub fn shutdown(&self) {
+        if let Some(reporter) = &self.runtime_metrics {
+            reporter.stop();
+        }
+        if let Some(reporter) = &self.dropped_lines {
+            reporter.stop();
+        }
         if let Some(provider) = &self.provider {
             let _ = provider.shutdown();
         }
+        crate::metrics::flush();
     }
 }
@@ -0,0 +1,191 @@
+//! Manual sampling decisions for the current trace.
+//!
+//! [`keep_current`] and [`drop_current`] override whatever the [sampler](crate::sampler)
+//! decided, by setting the sampling priority to `USER_KEEP` (2) or `USER_REJECT` (-1) on the
+//! current span and every span above it, up to the local root. Spans started afterwards and
+//! the `x-datadog-sampling-priority` header injected by
+//! [`DatadogPropagator`](crate::propagator::DatadogPropagator) carry the new priority.
+//!
+//! Call them before the spans of the trace end: spans that have already been exported keep
+//! the decision they were exported with.
+//!
+//! ```no_run
+//! #[tracing::instrument]
+//! async fn charge(amount: u64) {
+//!     if amount > 10_000 {
+//!         dogdata::trace::keep_current();
+//!     }
+//! }
+//! ```
+
+use opentelemetry::trace::{SamplingDecision, SamplingResult, SpanContext, TraceContextExt};
+use tracing::dispatcher;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::Registry;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::sampler::{USER_KEEP, USER_REJECT, sampling_priority, with_sampling_priority};
+
+/// Keeps the current trace, whatever the sampler decided.
+pub fn keep_current() {
+    set_current_priority(USER_KEEP);
+}
+
+/// Drops the current trace, whatever the sampler decided.
+pub fn drop_current() {
+    set_current_priority(USER_REJECT);
+}
+
+/// Sets the sampling priority of the current span and its ancestors. Does nothing outside
+/// of a span, or when the subscriber is not built on a [`Registry`].
+fn set_current_priority(priority: i8) {
+    dispatcher::get_default(|dispatch| {
+        let Some(id) = dispatch.current_span().id().cloned() else {
+            return;
+        };
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        let Some(span) = registry.span(&id) else {
+            return;
+        };
+
+        for span in span.scope() {
+            if let Some(data) = span.extensions_mut().get_mut::<OtelData>() {
+                set_priority(data, priority);
+            }
+        }
+    });
+}
+
+fn set_priority(data: &mut OtelData, priority: i8) {
+    let decision = if priority > 0 {
+        SamplingDecision::RecordAndSample
+    } else {
+        SamplingDecision::RecordOnly
+    };
+
+    // the span was either sampled already, or will inherit from its parent when it is
+    let (trace_state, attributes) = match data.builder.sampling_result.take() {
+        Some(result) => (result.trace_state, result.attributes),
+        None => (
+            data.parent_cx.span().span_context().trace_state().clone(),
+            Vec::new(),
+        ),
+    };
+    data.builder.sampling_result = Some(SamplingResult {
+        decision,
+        attributes,
+        trace_state: with_sampling_priority(&trace_state, priority),
+    });
+
+    // a local parent's context was captured when this span started, keep it in line
+    let parent = data.parent_cx.span().span_context().clone();
+    if parent.is_valid()
+        && !parent.is_remote()
+        && sampling_priority(parent.trace_state()) != Some(priority)
+    {
+        data.parent_cx = data.parent_cx.with_remote_span_context(SpanContext::new(
+            parent.trace_id(),
+            parent.span_id(),
+            parent.trace_flags().with_sampled(priority > 0),
+            false,
+            with_sampling_priority(parent.trace_state(), priority),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DogdataConfig;
+    use crate::exporter::tests::CapturingExporter;
+    use crate::propagator::DatadogPropagator;
+    use crate::sampler::{AgentRates, DatadogSampler};
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::collections::HashMap;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn provider(config: DogdataConfig, exporter: CapturingExporter) -> SdkTracerProvider {
+        SdkTracerProvider::builder()
+            .with_sampler(DatadogSampler::new(
+                &config,
+                "billing",
+                AgentRates::default(),
+            ))
+            .with_simple_exporter(exporter)
+            .build()
+    }
+
+    fn injected_priority() -> Option<String> {
+        let mut headers = HashMap::new();
+        DatadogPropagator::new().inject_context(&tracing::Span::current().context(), &mut headers);
+        headers.remove("x-datadog-sampling-priority")
+    }
+
+    #[test]
+    fn test_keep_current_overrides_sampler() {
+        let exporter = CapturingExporter::default();
+        let provider = provider(
+            DogdataConfig::default().with_sample_rate(0.0),
+            exporter.clone(),
+        );
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            let child = tracing::info_span!("child");
+            let _child = child.enter();
+            assert_eq!(injected_priority().as_deref(), Some("-1"));
+
+            keep_current();
+
+            assert_eq!(injected_priority().as_deref(), Some("2"));
+            let grandchild = tracing::info_span!("grandchild");
+            let _grandchild = grandchild.enter();
+            assert_eq!(injected_priority().as_deref(), Some("2"));
+        });
+
+        let spans = exporter.0.lock().unwrap();
+        let mut names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        names.sort();
+        assert_eq!(names, vec!["child", "grandchild", "root"]);
+        assert!(
+            spans
+                .iter()
+                .all(|span| sampling_priority(span.span_context.trace_state()) == Some(USER_KEEP))
+        );
+    }
+
+    #[test]
+    fn test_drop_current_overrides_sampler() {
+        let exporter = CapturingExporter::default();
+        let provider = provider(DogdataConfig::default(), exporter.clone());
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            let child = tracing::info_span!("child");
+            let _child = child.enter();
+
+            drop_current();
+
+            assert_eq!(injected_priority().as_deref(), Some("-1"));
+        });
+
+        assert!(exporter.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_outside_of_span_is_noop() {
+        keep_current();
+        drop_current();
+    }
+}
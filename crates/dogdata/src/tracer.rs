@@ -1,116 +1,264 @@
 //! Trace and layer builders to export traces to the Datadog agent.
 //!
 //! This module contains a function that builds a tracer with an exporter
-//! to send traces to the Datadog agent in batches over gRPC.
+//! to send traces to the Datadog agent in batches, to an OTLP endpoint, or to both,
+//! as selected by `DD_EXPORTER`.
 //!
 //! It also contains a convenience function to build a layer with the tracer.
 
 use opentelemetry::InstrumentationScope;
+use opentelemetry::KeyValue;
 use opentelemetry::global;
+use opentelemetry::trace::SpanId;
 use opentelemetry::trace::TraceError;
 pub use opentelemetry::trace::TraceId;
 use opentelemetry::trace::TraceResult;
 use opentelemetry::trace::TracerProvider;
-use opentelemetry_datadog::{ApiVersion, DatadogPropagator};
+use opentelemetry_sdk::Resource;
 use opentelemetry_sdk::runtime;
 use opentelemetry_sdk::trace::span_processor_with_async_runtime;
-use opentelemetry_sdk::trace::{self, SdkTracerProvider};
-use opentelemetry_sdk::trace::{RandomIdGenerator, Sampler, Tracer};
+use opentelemetry_sdk::trace::{IdGenerator, RandomIdGenerator, Tracer, TracerProviderBuilder};
+use opentelemetry_sdk::trace::{SdkTracerProvider, SimpleSpanProcessor, SpanProcessor};
 use opentelemetry_semantic_conventions as semcov;
-use std::env;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::Subscriber;
 use tracing_opentelemetry::{OpenTelemetryLayer, PreSampledTracer};
 use tracing_subscriber::registry::LookupSpan;
 
+use crate::agent::AgentEndpoint;
+use crate::baggage::BaggageTagProcessor;
+use crate::config::DogdataConfig;
+#[cfg(feature = "otlp")]
+use crate::exporter::OtlpExporter;
+use crate::exporter::{ConsoleExporter, DatadogExporter, Mapping, StatsProcessor, UnifiedTags};
 use crate::init::ModelMappings;
 use crate::model::default_name_mapping;
 use crate::model::default_resource_mapping;
 use crate::model::default_service_name_mapping;
+use crate::propagator::CompositePropagator;
+use crate::redaction::{RedactionProcessor, Redactor};
+use crate::sampler::{AgentRates, DatadogSampler, RejectedTraceProcessor};
 
-pub fn build_tracer_provider(mappings: Option<ModelMappings>) -> TraceResult<SdkTracerProvider> {
-    let service_name = env::var("DD_SERVICE")
-        .map_err(|_| <&str as Into<TraceError>>::into("missing DD_SERVICE"))?;
+pub fn build_tracer_provider(config: &DogdataConfig) -> TraceResult<SdkTracerProvider> {
+    let provider = tracer_provider(config)?;
+    install_globals(config, &provider);
+    Ok(provider)
+}
 
-    let dd_host = env::var("DD_AGENT_HOST").unwrap_or("localhost".to_string());
-    let dd_port = env::var("DD_AGENT_PORT")
-        .ok()
-        .and_then(|it| it.parse::<i32>().ok())
-        .unwrap_or(8126);
+/// Sets `provider` as the global tracer provider, along with the propagator selected by
+/// the configuration.
+pub(crate) fn install_globals(config: &DogdataConfig, provider: &SdkTracerProvider) {
+    global::set_tracer_provider(provider.clone());
 
-    // disabling connection reuse with dd-agent to avoid "connection closed from server" errors
-    let dd_http_client = reqwest::ClientBuilder::new()
-        .pool_idle_timeout(Duration::from_millis(1))
-        .build()
-        .expect("Could not init datadog http_client");
-
-    let mut config = trace::Config::default();
-    config.sampler = Box::new(Sampler::AlwaysOn);
-    config.id_generator = Box::new(RandomIdGenerator::default());
-
-    let mut pipeline = opentelemetry_datadog::new_pipeline()
-        .with_http_client(dd_http_client)
-        .with_service_name(service_name)
-        .with_api_version(ApiVersion::Version05)
-        .with_agent_endpoint(format!("http://{dd_host}:{dd_port}"))
-        .with_trace_config(config);
-
-    if let Some(mappings) = mappings {
-        pipeline = pipeline
-            .with_name_mapping(
-                mappings
-                    .name_mapping
-                    .unwrap_or_else(|| Box::new(default_name_mapping)),
-            )
-            .with_service_name_mapping(
-                mappings
-                    .service_name_mapping
-                    .unwrap_or_else(|| Box::new(default_service_name_mapping)),
+    global::set_text_map_propagator(CompositePropagator::new(
+        config.propagation_style_extract(),
+        config.propagation_style_inject(),
+    ));
+}
+
+/// Builds the tracer provider without touching the global OpenTelemetry state.
+pub(crate) fn tracer_provider(config: &DogdataConfig) -> TraceResult<SdkTracerProvider> {
+    let service_name = config
+        .service()
+        .ok_or_else(|| <&str as Into<TraceError>>::into("missing DD_SERVICE"))?;
+
+    let ModelMappings {
+        service_name_mapping,
+        name_mapping,
+        resource_mapping,
+    } = config.mappings.clone();
+    let mapping = Mapping {
+        service_name: service_name_mapping
+            .unwrap_or_else(|| Arc::new(default_service_name_mapping)),
+        name: name_mapping.unwrap_or_else(|| Arc::new(default_name_mapping)),
+        resource: resource_mapping.unwrap_or_else(|| Arc::new(default_resource_mapping)),
+    };
+    let unified_tags = UnifiedTags {
+        env: config.env().map(str::to_string),
+        version: config.version().map(str::to_string),
+    };
+
+    // the agent answers trace submissions with the rates the sampler should apply
+    let agent_rates = AgentRates::default();
+
+    let mut provider = SdkTracerProvider::builder()
+        .with_resource(build_resource(config, service_name))
+        .with_sampler(DatadogSampler::new(
+            config,
+            service_name,
+            agent_rates.clone(),
+        ));
+    if !config.baggage_tag_keys().is_empty() {
+        provider =
+            provider.with_span_processor(BaggageTagProcessor::new(config.baggage_tag_keys()));
+    }
+    // with only the console exporter, nothing is sent anywhere: a local setup without agent
+    let export = config.enabled() || config.console_exporter().is_none();
+    if let Some(format) = config.console_exporter() {
+        let exporter =
+            ConsoleExporter::new(format, service_name, mapping.clone(), unified_tags.clone());
+        provider = with_processor(
+            provider,
+            SimpleSpanProcessor::new(Box::new(exporter)),
+            config.redactor(),
+        );
+    }
+    if export && config.exporter().datadog() {
+        let agent = config
+            .agent_endpoint()
+            .parse::<AgentEndpoint>()
+            .map_err(<String as Into<TraceError>>::into)?;
+        let exporter = DatadogExporter::new(
+            &agent,
+            service_name,
+            mapping.clone(),
+            unified_tags.clone(),
+            agent_rates,
+        )?
+        .with_client_computed_stats(config.stats_computation_enabled());
+        if config.stats_computation_enabled() {
+            provider = with_processor(
+                provider,
+                StatsProcessor::new(&agent, service_name, mapping.clone(), unified_tags)?,
+                config.redactor(),
+            );
+        }
+        let processor = span_processor_with_async_runtime::BatchSpanProcessor::builder(
+            exporter,
+            runtime::Tokio,
+        )
+        .build();
+        provider = if config.stats_computation_enabled() {
+            with_processor(provider, processor, config.redactor())
+        } else {
+            with_processor(
+                provider,
+                RejectedTraceProcessor::new(processor),
+                config.redactor(),
             )
-            .with_resource_mapping(
-                mappings
-                    .resource_mapping
-                    .unwrap_or_else(|| Box::new(default_resource_mapping)),
+        };
+    }
+    if export && config.exporter().otlp() {
+        #[cfg(feature = "otlp")]
+        {
+            let exporter = OtlpExporter::new(
+                config.otlp_endpoint(),
+                config.otlp_protocol(),
+                service_name,
+                mapping,
+            )?;
+            provider = with_processor(
+                provider,
+                span_processor_with_async_runtime::BatchSpanProcessor::builder(
+                    exporter,
+                    runtime::Tokio,
+                )
+                .build(),
+                config.redactor(),
             );
+        }
+        #[cfg(not(feature = "otlp"))]
+        return Err("DD_EXPORTER=otlp requires the `otlp` feature".into());
+    }
+    provider = if config.trace_id_128_bit() {
+        provider.with_id_generator(DatadogIdGenerator::default())
+    } else {
+        provider.with_id_generator(RandomIdGenerator::default())
+    };
+    Ok(provider.build())
+}
+
+/// Adds an exporting processor, behind a [`RedactionProcessor`] when redaction is enabled.
+/// Every processor receives its own copy of the spans, so each of them has to be wrapped.
+fn with_processor<P>(
+    provider: TracerProviderBuilder,
+    processor: P,
+    redactor: Option<&Redactor>,
+) -> TracerProviderBuilder
+where
+    P: SpanProcessor + 'static,
+{
+    match redactor {
+        Some(redactor) => {
+            provider.with_span_processor(RedactionProcessor::new(processor, redactor.clone()))
+        }
+        None => provider.with_span_processor(processor),
     }
+}
 
-    let exporter = pipeline.build_exporter()?;
+/// Generates 128-bit trace ids the way Datadog's tracers do: the upper 32 bits hold the
+/// start time in seconds, followed by 32 zero bits and 64 random bits.
+#[derive(Debug, Default)]
+struct DatadogIdGenerator {
+    random: RandomIdGenerator,
+}
 
-    let provider = SdkTracerProvider::builder()
-        .with_span_processor(
-            span_processor_with_async_runtime::BatchSpanProcessor::builder(
-                exporter,
-                runtime::Tokio,
-            )
-            .build(),
-        )
-        .build();
-    global::set_tracer_provider(provider.clone());
+impl IdGenerator for DatadogIdGenerator {
+    fn new_trace_id(&self) -> TraceId {
+        let random = u128::from_be_bytes(self.random.new_trace_id().to_bytes()) as u64;
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |it| it.as_secs() as u32);
+        TraceId::from(((seconds as u128) << 96) | random as u128)
+    }
 
-    global::set_text_map_propagator(DatadogPropagator::default());
+    fn new_span_id(&self) -> SpanId {
+        self.random.new_span_id()
+    }
+}
 
-    Ok(provider)
+// `semcov::resource::DEPLOYMENT_ENVIRONMENT_NAME` is still behind the experimental feature
+const DEPLOYMENT_ENVIRONMENT_NAME: &str = "deployment.environment.name";
+
+/// Resource attributes attached to every exported span: the unified service tags plus the
+/// global tags from `DD_TAGS`.
+fn build_resource(config: &DogdataConfig, service_name: &str) -> Resource {
+    let mut attributes = Vec::new();
+    if let Some(env) = config.env() {
+        attributes.push(KeyValue::new(DEPLOYMENT_ENVIRONMENT_NAME, env.to_string()));
+    }
+    if let Some(version) = config.version() {
+        attributes.push(KeyValue::new(
+            semcov::resource::SERVICE_VERSION,
+            version.to_string(),
+        ));
+    }
+    attributes.extend(
+        config
+            .tags()
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "service" | "env" | "version"))
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+    );
+
+    Resource::builder()
+        .with_service_name(service_name.to_string())
+        .with_attributes(attributes)
+        .build()
 }
 
-pub fn build_tracer(mappings: Option<ModelMappings>) -> TraceResult<(Tracer, SdkTracerProvider)> {
-    let provider = build_tracer_provider(mappings)?;
+pub fn build_tracer(config: &DogdataConfig) -> TraceResult<(Tracer, SdkTracerProvider)> {
+    let provider = build_tracer_provider(config)?;
+    Ok((tracer(&provider), provider))
+}
 
+/// The tracer of this crate's instrumentation scope.
+pub(crate) fn tracer(provider: &SdkTracerProvider) -> Tracer {
     let scope = InstrumentationScope::builder(env!("CARGO_PKG_NAME"))
         .with_version(env!("CARGO_PKG_VERSION"))
         .with_schema_url(semcov::SCHEMA_URL)
         .with_attributes(None)
         .build();
 
-    let tracer = provider.tracer_with_scope(scope);
-
-    Ok((tracer, provider))
+    provider.tracer_with_scope(scope)
 }
 
-pub fn build_layer<S>() -> TraceResult<OpenTelemetryLayer<S, Tracer>>
+pub fn build_layer<S>(config: &DogdataConfig) -> TraceResult<OpenTelemetryLayer<S, Tracer>>
 where
     Tracer: opentelemetry::trace::Tracer + PreSampledTracer + 'static,
     S: Subscriber + for<'span> LookupSpan<'span>,
 {
-    let (tracer, _) = build_tracer(None)?;
+    let (tracer, _) = build_tracer(config)?;
     Ok(tracing_opentelemetry::layer().with_tracer(tracer))
 }
@@ -0,0 +1,13 @@
+//! Installs the global subscriber, so it runs in its own process.
+
+use dogdata::DogdataConfig;
+use dogdata::init::InitError;
+
+#[test]
+fn test_try_init_fails_when_subscriber_is_installed() {
+    let (_guard, shutdown, _log_level) = dogdata::try_init(DogdataConfig::default()).unwrap();
+
+    let result = dogdata::try_init(DogdataConfig::default());
+    assert!(matches!(result, Err(InitError::SubscriberInit(_))));
+    shutdown.shutdown();
+}
@@ -20,7 +20,8 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use dogdata::axum::{OtelAxumLayer, OtelInResponseLayer};
+use dogdata::DogdataConfig;
+use dogdata::axum::{OtelAxumLayer, OtelInResponseLayer, log_level_router};
 use std::net::SocketAddr;
 use std::time::Duration;
 
@@ -32,7 +33,7 @@ use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (_guard, tracer_shutdown) = dogdata::init(None)?;
+    let (_guard, tracer_shutdown, log_level) = dogdata::init(DogdataConfig::from_env())?;
 
     let app = Router::new()
         .route("/", get(root))
@@ -45,7 +46,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // requests don't hang forever.
             TimeoutLayer::new(Duration::from_secs(90)),
         ))
-        .route("/health", get(health));
+        .route("/health", get(health))
+        // GET/PUT the log directives, keep it off public listeners in production
+        .nest("/admin/log-level", log_level_router(log_level));
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3025));
     let listener = TcpListener::bind(addr).await?;
@@ -1,10 +1,9 @@
+use dogdata::DogdataConfig;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        std::env::set_var("RUST_LOG", "trace");
-    }
-
-    let (_guard, shutdown) = dogdata::init(None)?;
+    let config = DogdataConfig::from_env().with_log_directives("trace");
+    let (_guard, shutdown, _log_level) = dogdata::init(config)?;
 
     tracing::trace!("This is a trace message");
     tracing::debug!("This is a debug message");
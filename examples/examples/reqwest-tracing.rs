@@ -1,8 +1,9 @@
+use dogdata::DogdataConfig;
 use dogdata_reqwest_middleware::{SpanBackendWithUrl, TracingMiddleware};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (_guard, shutdown) = dogdata::init(None)?;
+    let (_guard, shutdown, _log_level) = dogdata::init(DogdataConfig::from_env())?;
 
     let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
         .with(TracingMiddleware::<SpanBackendWithUrl>::new())